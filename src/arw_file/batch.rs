@@ -0,0 +1,269 @@
+// Batch mode: the paths given on the command line expanded into files,
+// directories included, and a command run over them on a pool of threads.
+// The output of each file is printed in one piece and in path order, and a
+// file that fails is reported without stopping the others.
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Condvar, Mutex, PoisonError};
+use std::thread;
+
+use arw_file::limits::Limits;
+
+// Extensions of the files read from directories when none are given
+pub const EXTENSIONS: [&str; 11] =
+    ["arw", "sr2", "srf", "dng", "tif", "tiff", "jpg", "jpeg", "heic", "heif", "hif"];
+
+pub struct Options {
+    // Descend into subdirectories
+    pub recursive: bool,
+    // Extensions of the files read from directories, case insensitive
+    pub extensions: Vec<String>,
+    pub jobs: usize,
+    // Applied to each file
+    pub limits: Limits,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            recursive: false,
+            extensions: vec![],
+            jobs: thread::available_parallelism().map_or(1, |jobs| jobs.get()),
+            limits: Limits::default(),
+        }
+    }
+}
+
+impl Options {
+    fn wanted(&self, path: &Path) -> bool {
+        let extension = match path.extension().and_then(|e| e.to_str()) {
+            Some(extension) => extension,
+            None => return false,
+        };
+        if self.extensions.is_empty() {
+            EXTENSIONS.iter().any(|e| e.eq_ignore_ascii_case(extension))
+        } else {
+            self.extensions
+                .iter()
+                .any(|e| e.trim_start_matches('.').eq_ignore_ascii_case(extension))
+        }
+    }
+
+    // Files of `dir` in name order, then those of its subdirectories
+    fn walk(&self, dir: &Path, files: &mut Vec<String>, errors: &mut Vec<(String, io::Error)>) {
+        let entries = fs::read_dir(dir).and_then(|entries| entries.collect::<io::Result<Vec<_>>>());
+        let entries = match entries {
+            Ok(entries) => entries,
+            Err(err) => {
+                errors.push((dir.display().to_string(), err));
+                return;
+            }
+        };
+        let mut paths: Vec<_> = entries.iter().map(|entry| entry.path()).collect();
+        paths.sort();
+        let mut subdirs = vec![];
+        for path in paths {
+            // Symbolic links to directories are not followed, to avoid cycles
+            let is_dir = fs::symlink_metadata(&path).map(|m| m.is_dir()).unwrap_or(false);
+            if is_dir {
+                subdirs.push(path);
+            } else if path.is_file() && self.wanted(&path) {
+                files.push(path.display().to_string());
+            }
+        }
+        if self.recursive {
+            for subdir in subdirs {
+                self.walk(&subdir, files, errors);
+            }
+        }
+    }
+
+    // The files named by `paths`, directories replaced by the files in them
+    // with one of the extensions, and the paths that could not be read
+    pub fn files(&self, paths: &[String]) -> (Vec<String>, Vec<(String, io::Error)>) {
+        let (mut files, mut errors) = (vec![], vec![]);
+        for path in paths {
+            match fs::metadata(path) {
+                Ok(ref metadata) if metadata.is_dir() => {
+                    self.walk(Path::new(path), &mut files, &mut errors)
+                }
+                Ok(_) => files.push(path.clone()),
+                Err(err) => errors.push((path.clone(), err)),
+            }
+        }
+        (files, errors)
+    }
+}
+
+fn panic_error(payload: Box<dyn std::any::Any + Send>) -> io::Error {
+    let message = payload.downcast_ref::<&str>()
+        .map(|m| m.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_default();
+    io::Error::new(io::ErrorKind::InvalidData, format!("Parsing failed: {}", message))
+}
+
+// Files written or reported so far. Workers claim files at most `window`
+// ahead of them, bounding the outputs held back while a file is slow.
+struct Progress {
+    done: Mutex<usize>,
+    changed: Condvar,
+    window: usize,
+}
+
+impl Progress {
+    // Blocks until file `i` is inside the window
+    fn wait_for(&self, i: usize) {
+        let mut done = self.done.lock().unwrap_or_else(PoisonError::into_inner);
+        while i >= done.saturating_add(self.window) {
+            done = self.changed.wait(done).unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    // usize::MAX releases all the workers
+    fn set(&self, done: usize) {
+        *self.done.lock().unwrap_or_else(PoisonError::into_inner) = done;
+        self.changed.notify_all();
+    }
+}
+
+// Runs `command` over `files` on `jobs` threads, writing the output of each
+// file to `out` whole, in the order of `files`, with `separator` between
+// them. Failures, panics included, are reported on stderr with the file
+// name. Returns the number of them.
+pub fn run<W, F>(files: &[String],
+                 jobs: usize,
+                 separator: &str,
+                 out: &mut W,
+                 command: F)
+                 -> io::Result<usize>
+    where W: Write,
+          F: Fn(&str, &mut Vec<u8>) -> io::Result<()> + Sync
+{
+    let next = AtomicUsize::new(0);
+    let progress = Progress {
+        done: Mutex::new(0),
+        changed: Condvar::new(),
+        window: jobs.max(1) * 4,
+    };
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..jobs.max(1).min(files.len()) {
+            let sender = sender.clone();
+            let (next, progress, command) = (&next, &progress, &command);
+            scope.spawn(move || loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                if i >= files.len() {
+                    break;
+                }
+                progress.wait_for(i);
+                let mut buffer = vec![];
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                        command(&files[i], &mut buffer)
+                    }))
+                    .unwrap_or_else(|payload| Err(panic_error(payload)))
+                    .map(|_| buffer);
+                // The receiver is gone when writing the output failed
+                if sender.send((i, result)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        let result = write_in_order(files, receiver, separator, out, &progress);
+        // Workers waiting for a window that no longer moves
+        progress.set(usize::MAX);
+        result
+    })
+}
+
+// Writes the outputs received as the earlier ones are written, returning
+// the number of failures
+fn write_in_order<W: Write>(files: &[String],
+                            receiver: mpsc::Receiver<(usize, io::Result<Vec<u8>>)>,
+                            separator: &str,
+                            out: &mut W,
+                            progress: &Progress)
+                            -> io::Result<usize> {
+    // Outputs that arrived ahead of an earlier file's
+    let mut pending = BTreeMap::new();
+    let (mut done, mut written, mut failures) = (0, 0, 0);
+    for (i, result) in receiver {
+        pending.insert(i, result);
+        while let Some(result) = pending.remove(&done) {
+            match result {
+                Ok(buffer) => {
+                    if written > 0 {
+                        out.write_all(separator.as_bytes())?;
+                    }
+                    out.write_all(&buffer)?;
+                    written += 1;
+                }
+                Err(err) => {
+                    eprintln!("{}: {}", files[done], err);
+                    failures += 1;
+                }
+            }
+            done += 1;
+            progress.set(done);
+        }
+    }
+    Ok(failures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_run() {
+        let files: Vec<String> = (0..20).map(|i| i.to_string()).collect();
+        let mut out = vec![];
+        let failures = run(&files, 4, ",", &mut out, |file, buffer| {
+            let n: u32 = file.parse().unwrap();
+            if n % 7 == 3 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "odd one"));
+            }
+            if n == 5 {
+                panic!("five");
+            }
+            write!(buffer, "{}", n)
+        });
+        assert_eq!(failures.unwrap(), 4);
+        assert_eq!(String::from_utf8(out).unwrap(), "0,1,2,4,6,7,8,9,11,12,13,14,15,16,18,19");
+    }
+
+    #[test]
+    fn test_run_bounded() {
+        // Files claimed while the first one is held back
+        let claimed = AtomicUsize::new(0);
+        let files: Vec<String> = (0..100).map(|i| i.to_string()).collect();
+        let failures = run(&files, 2, "", &mut vec![], |file, _| {
+            if file == "0" {
+                thread::sleep(Duration::from_millis(100));
+                assert!(claimed.load(Ordering::SeqCst) <= 8);
+            } else {
+                claimed.fetch_add(1, Ordering::SeqCst);
+            }
+            Ok(())
+        });
+        assert_eq!(failures.unwrap(), 0);
+        assert_eq!(claimed.load(Ordering::SeqCst), 99);
+    }
+
+    #[test]
+    fn test_wanted() {
+        let options = Options::default();
+        assert!(options.wanted(Path::new("a/DSC0001.ARW")));
+        assert!(!options.wanted(Path::new("a/DSC0001.xmp")));
+        let options = Options { extensions: vec![String::from(".xmp")], ..Options::default() };
+        assert!(options.wanted(Path::new("a/DSC0001.xmp")));
+        assert!(!options.wanted(Path::new("a/DSC0001")));
+    }
+}
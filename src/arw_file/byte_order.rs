@@ -1,4 +1,5 @@
 #[derive(PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum ByteOrder {
     LittleEndian,
     BigEndian,
@@ -47,6 +48,14 @@ impl ByteOrder {
         (-((input_value & mask) as i64) + (input_value & !mask) as i64) as i32
     }
 
+    pub fn u16_to_slice(&self, val: u16) -> [u8; 2] {
+        if *self == ByteOrder::LittleEndian {
+            [(val & 0xFF) as u8, ((val >> 8) & 0xFF) as u8]
+        } else {
+            [((val >> 8) & 0xFF) as u8, (val & 0xFF) as u8]
+        }
+    }
+
     pub fn u32_to_slice(&self, val: u32) -> [u8; 4] {
         let mut buf: [u8; 4] = [0, 0, 0, 0];
         if *self == ByteOrder::LittleEndian {
@@ -116,6 +125,18 @@ mod tests {
         assert_eq!(bo.parse_i32(&[0, 255, 255, 255]), -256);
     }
 
+    #[test]
+    fn test_be_u16_to_slice() {
+        let bo = ByteOrder::BigEndian;
+        assert_eq!(bo.u16_to_slice(0x100), [1, 0]);
+    }
+
+    #[test]
+    fn test_le_u16_to_slice() {
+        let bo = ByteOrder::LittleEndian;
+        assert_eq!(bo.u16_to_slice(0x100), [0, 1]);
+    }
+
     #[test]
     fn test_be_u32_to_slice() {
         let bo = ByteOrder::BigEndian;
@@ -0,0 +1,178 @@
+// Tabular export: one row per file, one column per selected tag, as CSV
+// (RFC 4180 quoting) or TSV
+use std::io;
+
+use arw_file::header::Header;
+use arw_file::ifd::tag;
+use arw_file::ifd::IFDEntry;
+
+// Short names for common Exif tags, as exiftool prints them
+const ALIASES: [(&str, u16); 4] = [("ISO", 34855),
+                                   ("DateTimeOriginal", 36867),
+                                   ("LensModel", 42036),
+                                   ("SerialNumber", 42033)];
+
+pub struct Column {
+    pub title: String,
+    pub tag_id: u16,
+    // Looked up in the MakerNote rather than the TIFF and Exif IFDs
+    pub sony: bool,
+}
+
+impl Column {
+    // From a label (standard tags first, then Sony ones), an alias or an id
+    pub fn parse(spec: &str) -> io::Result<Column> {
+        let alias = ALIASES.iter().find(|&&(name, _)| name.eq_ignore_ascii_case(spec));
+        let (tag_id, sony) = if let Some(&(_, tag_id)) = alias {
+            (tag_id, false)
+        } else if let Some(tag_id) = tag::parse_id(spec, false) {
+            (tag_id, false)
+        } else if let Some(tag) = tag::find_by_label(spec, true) {
+            (tag.id, true)
+        } else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!("Unknown tag {}", spec)));
+        };
+        Ok(Column {
+            title: String::from(spec),
+            tag_id,
+            sony,
+        })
+    }
+
+    pub fn entry<'a>(&self, header: &'a Header) -> Option<&'a IFDEntry> {
+        header.ifds
+            .iter()
+            .filter(|ifd| (ifd.ifd_type == "MakerNote") == self.sony)
+            .filter_map(|ifd| ifd.entry(self.tag_id))
+            .next()
+    }
+}
+
+// Rationals as exiftool shows them: 1/250 for exposure times, 2.8 otherwise,
+// undef for 0/0 and inf for other zero denominators
+fn rational(numerator: i64, denominator: i64) -> String {
+    if denominator == 0 {
+        return String::from(if numerator == 0 { "undef" } else { "inf" });
+    }
+    let value = numerator as f64 / denominator as f64;
+    if numerator != 0 && value.abs() < 1.0 && denominator % numerator == 0 {
+        let sign = if value < 0.0 { "-" } else { "" };
+        format!("{}1/{}", sign, (denominator / numerator).abs())
+    } else {
+        let text = format!("{:.4}", value);
+        String::from(text.trim_end_matches('0').trim_end_matches('.'))
+    }
+}
+
+// The interpreted value, several values separated by spaces
+pub fn value(entry: &IFDEntry) -> String {
+    fn join<T: ToString>(values: Vec<T>) -> String {
+        values.iter().map(|v| v.to_string()).collect::<Vec<String>>().join(" ")
+    }
+    let rationals = |signed: bool| -> String {
+        let order = entry.byte_order;
+        let values: Vec<String> = entry.value_bytes
+            .chunks(8)
+            .filter(|pair| pair.len() == 8)
+            .map(|pair| if signed {
+                rational(order.parse_i32(&pair[0..4]) as i64, order.parse_i32(&pair[4..8]) as i64)
+            } else {
+                rational(order.parse_u32(&pair[0..4]) as i64, order.parse_u32(&pair[4..8]) as i64)
+            })
+            .collect();
+        values.join(" ")
+    };
+    match entry.field_type.name() {
+        "ASCII" => {
+            let text = String::from_utf8_lossy(&entry.value_bytes);
+            String::from(text.trim_end_matches('\0').trim())
+        }
+        "BYTE" | "SHORT" | "LONG" => join(entry.unsigned_values().unwrap_or_default()),
+        "SSHORT" => join(entry.signed_short_values().unwrap_or_default()),
+        "SLONG" => join(entry.signed_long_values().unwrap_or_default()),
+        "RATIONAL" => rationals(false),
+        "SRATIONAL" => rationals(true),
+        _ => entry.value_bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+    }
+}
+
+// A field quoted for CSV when needed, or with tabs and line breaks replaced
+// by spaces for TSV, which has no quoting
+pub fn field(text: &str, separator: char) -> String {
+    if separator == '\t' {
+        return text.replace(['\t', '\n', '\r'], " ");
+    }
+    if text.contains([separator, '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        String::from(text)
+    }
+}
+
+pub fn row(fields: &[String], separator: char) -> String {
+    let fields: Vec<String> = fields.iter().map(|f| field(f, separator)).collect();
+    fields.join(&separator.to_string())
+}
+
+pub fn title_row(columns: &[Column], separator: char) -> String {
+    let mut titles = vec![String::from("File")];
+    titles.extend(columns.iter().map(|column| column.title.clone()));
+    row(&titles, separator)
+}
+
+// Missing tags leave the field empty
+pub fn file_row(filename: &str, header: &Header, columns: &[Column], separator: char) -> String {
+    let mut fields = vec![String::from(filename)];
+    fields.extend(columns.iter().map(|column| column.entry(header).map_or(String::new(), value)));
+    row(&fields, separator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arw_file::byte_order::ByteOrder;
+    use arw_file::writer::{self, TiffIfd, Value};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_columns() {
+        assert_eq!(Column::parse("iso").unwrap().tag_id, 34855);
+        assert_eq!(Column::parse("ExposureTime").unwrap().tag_id, 33434);
+        assert_eq!(Column::parse("0x0110").unwrap().tag_id, 272);
+        let lens = Column::parse("LensType").unwrap();
+        assert!(lens.sony);
+        // Also 45122 and 45134
+        assert_eq!(Column::parse("FocusMode").unwrap().tag_id, 8219);
+        assert_eq!(tag::parse_id("focusmode", true), Some(8219));
+        assert!(Column::parse("NoSuchTag").is_err());
+    }
+
+    #[test]
+    fn test_rational() {
+        assert_eq!(rational(10, 2500), "1/250");
+        assert_eq!(rational(28, 10), "2.8");
+        assert_eq!(rational(0, 0), "undef");
+        assert_eq!(rational(3, 0), "inf");
+    }
+
+    #[test]
+    fn test_rows() {
+        let mut ifd = TiffIfd::new();
+        ifd.set(272, Value::Ascii(String::from("ILCE-7M3, \"silver\"")));
+        ifd.set(33434, Value::Rational(vec![(10, 2500)]));
+        ifd.set(33437, Value::Rational(vec![(28, 10)]));
+        let mut f = Cursor::new(writer::encode(ByteOrder::LittleEndian, &[ifd]));
+        let header = Header::new(&mut f).unwrap();
+        let columns: Vec<Column> = ["Model", "ExposureTime", "FNumber", "ISO"]
+            .iter()
+            .map(|spec| Column::parse(spec).unwrap())
+            .collect();
+
+        assert_eq!(title_row(&columns, ','), "File,Model,ExposureTime,FNumber,ISO");
+        assert_eq!(file_row("a.arw", &header, &columns, ','),
+                   "a.arw,\"ILCE-7M3, \"\"silver\"\"\",1/250,2.8,");
+        assert_eq!(file_row("a.arw", &header, &columns, '\t'),
+                   "a.arw\tILCE-7M3, \"silver\"\t1/250\t2.8\t");
+    }
+}
@@ -0,0 +1,189 @@
+// Tags added, removed and changed between two files, per exiftool group
+// (IFD0, ExifIFD, Sony..), values compared as stored and shown interpreted
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use arw_file::exiftool;
+use arw_file::filter::Filter;
+use arw_file::ifd::{IFD, IFDEntry};
+
+// Tags expected to differ between any two shots: dates and times, serial
+// numbers and counters, by group ("" for any group outside GPS and Sony)
+const VOLATILE: [(&str, u16); 17] = [("", 306), // DateTime
+                                     ("", 36867), // DateTimeOriginal
+                                     ("", 36868), // DateTimeDigitized
+                                     ("", 36880), // OffsetTime
+                                     ("", 36881), // OffsetTimeOriginal
+                                     ("", 36882), // OffsetTimeDigitized
+                                     ("", 37520), // SubsecTime
+                                     ("", 37521), // SubsecTimeOriginal
+                                     ("", 37522), // SubsecTimeDigitized
+                                     ("", 42016), // ImageUniqueID
+                                     ("", 42033), // BodySerialNumber
+                                     ("", 42037), // LensSerialNumber
+                                     ("", 50735), // CameraSerialNumber
+                                     ("GPS", 7), // GPSTimeStamp
+                                     ("GPS", 29), // GPSDateStamp
+                                     ("Sony", 0x3000), // ShotInfo, holding the date
+                                     ("Sony", 0xb04a)]; // SequenceNumber
+
+// Offsets of image data, which move whenever anything before them grows
+const OFFSETS: [u16; 4] = [273, 324, 513, 0x7200];
+
+pub enum Change {
+    Added(String),
+    Removed(String),
+    // Old and new values
+    Changed(String, String),
+}
+
+pub struct TagDiff {
+    pub group: String,
+    pub name: String,
+    pub change: Change,
+}
+
+// Dates, serial numbers and offsets, including the pointers to other IFDs
+pub fn is_volatile(group: &str, entry: &IFDEntry) -> bool {
+    let namespace = match group {
+        "GPS" | "Sony" => group,
+        _ => "",
+    };
+    entry.tag.ifd || OFFSETS.contains(&entry.tag.id) ||
+    VOLATILE.contains(&(namespace, entry.tag.id))
+}
+
+fn entries<'a>(groups: &[(String, &'a IFD)],
+               filter: &Filter,
+               ignore_volatile: bool)
+               -> BTreeMap<(String, u16), &'a IFDEntry> {
+    let mut entries = BTreeMap::new();
+    for (group, ifd) in groups {
+        for entry in &ifd.entries {
+            // The MakerNote block is compared tag by tag under Sony
+            if entry.tag.id == exiftool::MAKER_NOTE ||
+               (ignore_volatile && is_volatile(group, entry)) ||
+               !filter.includes(group, &ifd.ifd_type, entry) {
+                continue;
+            }
+            entries.entry((group.clone(), entry.tag.id)).or_insert(entry);
+        }
+    }
+    entries
+}
+
+// Differences from the `a` groups to the `b` ones, in group order of `a`
+// (groups only in `b` last) and tag id order within a group
+pub fn diff(a: &[(String, &IFD)],
+            b: &[(String, &IFD)],
+            filter: &Filter,
+            ignore_volatile: bool)
+            -> Vec<TagDiff> {
+    let a_entries = entries(a, filter, ignore_volatile);
+    let b_entries = entries(b, filter, ignore_volatile);
+    let mut group_order: Vec<&str> = vec![];
+    for (group, _) in a.iter().chain(b.iter()) {
+        if !group_order.contains(&group.as_ref()) {
+            group_order.push(group);
+        }
+    }
+
+    let mut keys: Vec<&(String, u16)> = a_entries.keys().chain(b_entries.keys()).collect();
+    keys.sort_by_key(|&(group, id)| (group_order.iter().position(|g| g == group), *id));
+    keys.dedup();
+    keys.into_iter()
+        .filter_map(|key| {
+            let (group, _) = key;
+            let (entry, change) = match (a_entries.get(key), b_entries.get(key)) {
+                (Some(a), Some(b)) => {
+                    if a.field_type.id() == b.field_type.id() && a.value_bytes == b.value_bytes {
+                        return None;
+                    }
+                    (a, Change::Changed(exiftool::value(a), exiftool::value(b)))
+                }
+                (Some(a), None) => (a, Change::Removed(exiftool::value(a))),
+                (None, Some(b)) => (b, Change::Added(exiftool::value(b))),
+                (None, None) => return None,
+            };
+            Some(TagDiff {
+                group: group.clone(),
+                name: exiftool::tag_name(group, entry),
+                change,
+            })
+        })
+        .collect()
+}
+
+pub fn pretty_print<W: Write>(out: &mut W,
+                              a_name: &str,
+                              b_name: &str,
+                              diffs: &[TagDiff])
+                              -> io::Result<()> {
+    writeln!(out, "--- {}\n+++ {}", a_name, b_name)?;
+    if diffs.is_empty() {
+        return writeln!(out, "No differences");
+    }
+    let mut group = "";
+    for diff in diffs {
+        if diff.group != group {
+            group = &diff.group;
+            writeln!(out, "\n[{}]", group)?;
+        }
+        match diff.change {
+            Change::Added(ref value) => writeln!(out, "+ {}: {}", diff.name, value)?,
+            Change::Removed(ref value) => writeln!(out, "- {}: {}", diff.name, value)?,
+            Change::Changed(ref old, ref new) => {
+                writeln!(out, "~ {}: {} -> {}", diff.name, old, new)?
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arw_file::byte_order::ByteOrder;
+    use arw_file::header::Header;
+    use arw_file::writer::{self, TiffIfd, Value};
+    use std::io::Cursor;
+
+    fn header(exposure: (u32, u32), lens: Option<&str>, date: &str) -> Header {
+        let mut exif = TiffIfd::new();
+        exif.set(33434, Value::Rational(vec![exposure]));
+        exif.set(36867, Value::Ascii(String::from(date)));
+        if let Some(lens) = lens {
+            exif.set(42036, Value::Ascii(String::from(lens)));
+        }
+        let mut ifd0 = TiffIfd::new();
+        ifd0.set(271, Value::Ascii(String::from("SONY")));
+        ifd0.set(34665, Value::Ifds(vec![exif]));
+        let mut f = Cursor::new(writer::encode(ByteOrder::LittleEndian, &[ifd0]));
+        Header::new(&mut f).unwrap()
+    }
+
+    fn lines(diffs: &[TagDiff]) -> String {
+        let mut out = vec![];
+        pretty_print(&mut out, "a.arw", "b.arw", diffs).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_diff() {
+        let a = header((1, 250), None, "2024:01:01 10:00:00");
+        let b = header((1, 125), Some("FE 50mm F1.8"), "2024:01:01 10:00:05");
+        let (a_groups, b_groups) = (exiftool::groups(&a, &[]), exiftool::groups(&b, &[]));
+
+        let diffs = diff(&a_groups, &b_groups, &Filter::default(), true);
+        assert_eq!(lines(&diffs),
+                   "--- a.arw\n+++ b.arw\n\n[ExifIFD]\n~ ExposureTime: 1/250 -> 1/125\n\
+                    + LensModel: FE 50mm F1.8\n");
+
+        let diffs = diff(&a_groups, &b_groups, &Filter::default(), false);
+        assert!(lines(&diffs)
+            .contains("~ DateTimeOriginal: 2024:01:01 10:00:00 -> 2024:01:01 10:00:05\n"));
+
+        let diffs = diff(&b_groups, &b_groups, &Filter::default(), false);
+        assert_eq!(lines(&diffs), "--- a.arw\n+++ b.arw\nNo differences\n");
+    }
+}
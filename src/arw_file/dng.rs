@@ -0,0 +1,273 @@
+// ARW to DNG conversion: the decoded CFA data as an uncompressed raw SubIFD,
+// the largest embedded JPEG as the IFD0 preview, DNG colour tags and the
+// original Exif IFD, MakerNote included, carried over.
+use std::io;
+use std::io::{Read, Seek};
+
+use arw_file::byte_order::ByteOrder;
+use arw_file::export::{self, Levels};
+use arw_file::header::Header;
+use arw_file::ifd::{IFD, IFDEntry};
+use arw_file::preview::{self, JpegStatus};
+use arw_file::raw::{self, CfaImage, RawIfd};
+use arw_file::sr2;
+use arw_file::writer::{self, TiffIfd, Value};
+
+const MAKE: u16 = 271;
+const MODEL: u16 = 272;
+const EXIF_IFD: u16 = 34665;
+const GPS_IFD: u16 = 34853;
+const INTEROPERABILITY_IFD: u16 = 40965;
+const MAKER_NOTE: u16 = 37500;
+const CFA_PATTERN: u16 = 33422;
+const DEFAULT_CROP_ORIGIN: u16 = 50719;
+const DEFAULT_CROP_SIZE: u16 = 50720;
+// Copied from the source IFD0
+const IFD0_TAGS: [u16; 7] = [MAKE, MODEL, 274, 306, 315, 33432, 270];
+// SR2SubIFD white balance
+const WB_GRBG_LEVELS: u16 = 0x7303;
+const WB_RGGB_LEVELS: u16 = 0x7313;
+
+const ILLUMINANT_D65: u16 = 21;
+
+// XYZ to camera matrices (x 10000) under D65, as published by Adobe
+const COLOR_MATRICES: [(&str, [i32; 9]); 10] =
+    [("ILCE-7", [5271, -712, -347, -6153, 13653, 2763, -1601, 2366, 7242]),
+     ("ILCE-7M2", [5271, -712, -347, -6153, 13653, 2763, -1601, 2366, 7242]),
+     ("ILCE-7M3", [7374, -2389, -551, -5435, 13162, 2519, -1006, 1795, 6552]),
+     ("ILCE-7RM2", [6629, -1900, -483, -4618, 12349, 2550, -622, 1381, 6514]),
+     ("ILCE-7RM3", [6640, -1847, -503, -5238, 13010, 2474, -993, 1673, 6527]),
+     ("ILCE-7RM4", [7662, -2686, -660, -5240, 12965, 2530, -796, 1508, 6167]),
+     ("ILCE-7S", [5838, -1430, -246, -3497, 11477, 2297, -748, 1885, 5778]),
+     ("ILCE-9", [6389, -1703, -378, -4562, 12265, 2587, -670, 1489, 6550]),
+     ("ILCE-6000", [5991, -1456, -455, -4764, 12135, 2980, -707, 1425, 6701]),
+     ("DSC-RX100", [8651, -2754, -1057, -3464, 12207, 1373, -568, 1398, 4434])];
+
+// XYZ to linear sRGB, for cameras without a known matrix
+const SRGB_MATRIX: [i32; 9] = [32406, -15372, -4986, -9689, 18758, 415, 557, -2040, 10570];
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn ascii(entry: &IFDEntry) -> String {
+    entry.ascii_value().map_or(String::new(), |s| String::from(s.trim_end_matches('\0').trim()))
+}
+
+// Integer values of SHORT, LONG, SSHORT or SLONG entries
+fn int_values(entry: &IFDEntry) -> Option<Vec<i64>> {
+    entry.unsigned_values()
+        .map(|values| values.into_iter().map(|v| v as i64).collect())
+        .or_else(|| entry.signed_short_values().map(|v| v.into_iter().map(|v| v as i64).collect()))
+        .or_else(|| entry.signed_long_values().map(|v| v.into_iter().map(|v| v as i64).collect()))
+}
+
+pub fn color_matrix(model: &str) -> Option<[i32; 9]> {
+    COLOR_MATRICES.iter().find(|&&(name, _)| name == model).map(|&(_, matrix)| matrix)
+}
+
+// Camera neutral from the white balance multipliers, green being 1
+pub fn as_shot_neutral(header: &Header, sr2_sub_ifd: Option<&IFD>) -> Option<[(u32, u32); 3]> {
+    let ifds = sr2_sub_ifd.into_iter()
+        .chain(header.ifds.iter().filter(|ifd| ifd.ifd_type != "MakerNote"));
+    for ifd in ifds {
+        let rgb = if let Some(levels) = ifd.entry(WB_RGGB_LEVELS).and_then(int_values) {
+            levels.get(3).map(|blue| (levels[0], levels[1], *blue))
+        } else if let Some(levels) = ifd.entry(WB_GRBG_LEVELS).and_then(int_values) {
+            levels.get(3).map(|_| (levels[1], levels[0], levels[2]))
+        } else {
+            None
+        };
+        if let Some((red, green, blue)) = rgb {
+            if red > 0 && green > 0 && blue > 0 {
+                return Some([(green as u32, red as u32), (1, 1), (green as u32, blue as u32)]);
+            }
+        }
+    }
+    None
+}
+
+fn raw_ifd(raw: &RawIfd, image: &CfaImage, levels: &Levels, byte_order: ByteOrder) -> TiffIfd {
+    let mut strip = Vec::with_capacity(image.data.len() * 2);
+    for sample in &image.data {
+        strip.extend_from_slice(&byte_order.u16_to_slice(*sample));
+    }
+    let cfa_pattern = raw.ifd
+        .entry(CFA_PATTERN)
+        .filter(|entry| entry.value_bytes.len() == 4)
+        .map_or(vec![0, 1, 1, 2], |entry| entry.value_bytes.clone());
+
+    let mut ifd = TiffIfd::new();
+    ifd.set(254, Value::Long(vec![0]));
+    ifd.set(256, Value::Long(vec![image.width]));
+    ifd.set(257, Value::Long(vec![image.height]));
+    ifd.set(258, Value::Short(vec![16]));
+    ifd.set(259, Value::Short(vec![1]));
+    ifd.set(262, Value::Short(vec![32803]));
+    ifd.set(277, Value::Short(vec![1]));
+    ifd.set(278, Value::Long(vec![image.height]));
+    ifd.set(279, Value::Long(vec![strip.len() as u32]));
+    ifd.set(284, Value::Short(vec![1]));
+    ifd.set(33421, Value::Short(vec![2, 2]));
+    ifd.set(CFA_PATTERN, Value::Byte(cfa_pattern));
+    if levels.black.len() == 4 {
+        ifd.set(50713, Value::Short(vec![2, 2]));
+    }
+    if !levels.black.is_empty() {
+        ifd.set(50714, Value::Long(levels.black.clone()));
+    }
+    if let Some(white) = levels.white {
+        ifd.set(50717, Value::Long(vec![white]));
+    }
+    if let Some((x, y, width, height)) = export::crop_area(raw) {
+        ifd.set(DEFAULT_CROP_ORIGIN, Value::Long(vec![x, y]));
+        ifd.set(DEFAULT_CROP_SIZE, Value::Long(vec![width, height]));
+    }
+    ifd.set(273, Value::Offsets(vec![strip]));
+    ifd
+}
+
+// Exif IFD with its Interoperability IFD and MakerNote
+fn exif_ifd(header: &Header, exif: &IFD) -> TiffIfd {
+    let mut ifd = TiffIfd::from_ifd(exif);
+    let interop = exif.entry(INTEROPERABILITY_IFD).and_then(|e| header.ifd_at(e.value_offset));
+    if let Some(interop) = interop {
+        ifd.set(INTEROPERABILITY_IFD, Value::Ifds(vec![TiffIfd::from_ifd(interop)]));
+    }
+    if let Some(entry) = exif.entry(MAKER_NOTE) {
+        if let Some(maker_note) = header.ifd_at(entry.ifd_offset()) {
+            let prefix = entry.value_bytes[..entry.maker_note_header_length()].to_vec();
+            ifd.set(MAKER_NOTE,
+                    Value::MakerNote(prefix, Box::new(TiffIfd::from_ifd(maker_note))));
+        }
+    }
+    ifd
+}
+
+pub fn dng_bytes<R: Read + Seek>(f: &mut R, header: &Header) -> io::Result<Vec<u8>> {
+    let raw = match RawIfd::locate(header) {
+        Some(raw) => raw,
+        None => return Err(invalid_data(String::from("No raw image IFD found"))),
+    };
+    let image = raw::decode(f, header)?;
+    let byte_order = header.byte_order;
+    let sr2_sub_ifd = sr2::sr2_sub_ifd(f, header);
+    let levels = Levels::new(header, &raw, sr2_sub_ifd.as_ref());
+
+    let source_ifd0 = match header.ifds.first() {
+        Some(ifd) => ifd,
+        None => return Err(invalid_data(String::from("No IFD found"))),
+    };
+    let make = source_ifd0.entry(MAKE).map_or(String::new(), ascii);
+    let model = source_ifd0.entry(MODEL).map_or(String::new(), ascii);
+
+    let mut ifd0 = TiffIfd::new();
+    for tag_id in IFD0_TAGS.iter() {
+        if let Some(entry) = source_ifd0.entry(*tag_id) {
+            ifd0.set(*tag_id,
+                     Value::Raw(entry.field_type.id(), entry.count, entry.value_bytes.clone()));
+        }
+    }
+    ifd0.set(305, Value::Ascii(format!("arw_info {}", env!("CARGO_PKG_VERSION"))));
+    ifd0.set(50706, Value::Byte(vec![1, 4, 0, 0]));
+    ifd0.set(50707, Value::Byte(vec![1, 1, 0, 0]));
+    ifd0.set(50708, Value::Ascii(format!("{} {}", make, model).trim().to_string()));
+
+    let matrix = match color_matrix(&model) {
+        Some(matrix) => matrix,
+        None => {
+            eprintln!("Warning: no colour matrix for {}, using sRGB primaries", model);
+            SRGB_MATRIX
+        }
+    };
+    ifd0.set(50721, Value::SRational(matrix.iter().map(|v| (*v, 10000)).collect()));
+    ifd0.set(50778, Value::Short(vec![ILLUMINANT_D65]));
+    if let Some(neutral) = as_shot_neutral(header, sr2_sub_ifd.as_ref()) {
+        ifd0.set(50728, Value::Rational(neutral.to_vec()));
+    }
+
+    if let Some(exif) = source_ifd0.entry(EXIF_IFD).and_then(|e| header.ifd_at(e.value_offset)) {
+        ifd0.set(EXIF_IFD, Value::Ifds(vec![exif_ifd(header, exif)]));
+    }
+    if let Some(gps) = source_ifd0.entry(GPS_IFD).and_then(|e| header.ifd_at(e.value_offset)) {
+        ifd0.set(GPS_IFD, Value::Ifds(vec![TiffIfd::from_ifd(gps)]));
+    }
+
+    let raw_ifd = raw_ifd(&raw, &image, &levels, byte_order);
+
+    // The largest complete embedded JPEG becomes the IFD0 preview
+    let jpegs = preview::embedded_jpegs(f, header)?;
+    let largest = jpegs.iter()
+        .filter(|jpeg| jpeg.status == JpegStatus::Valid && jpeg.dimensions.is_some())
+        .max_by_key(|jpeg| jpeg.length);
+
+    match largest {
+        Some(jpeg) => {
+            let (width, height) = jpeg.dimensions.unwrap();
+            let data = preview::read_jpeg(f, jpeg)?;
+            ifd0.set(254, Value::Long(vec![1]));
+            ifd0.set(256, Value::Long(vec![width as u32]));
+            ifd0.set(257, Value::Long(vec![height as u32]));
+            ifd0.set(258, Value::Short(vec![8, 8, 8]));
+            ifd0.set(259, Value::Short(vec![7]));
+            ifd0.set(262, Value::Short(vec![6]));
+            ifd0.set(277, Value::Short(vec![3]));
+            ifd0.set(278, Value::Long(vec![height as u32]));
+            ifd0.set(279, Value::Long(vec![data.len() as u32]));
+            ifd0.set(284, Value::Short(vec![1]));
+            ifd0.set(273, Value::Offsets(vec![data]));
+            ifd0.set(330, Value::Ifds(vec![raw_ifd]));
+        }
+        None => {
+            // Without a preview the raw image goes in IFD0
+            ifd0.entries.extend(raw_ifd.entries);
+        }
+    }
+
+    Ok(writer::encode(byte_order, &[ifd0]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arw_file::read_at;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_dng_bytes() {
+        let mut sub_ifd = TiffIfd::new();
+        sub_ifd.set(0x7310, Value::Short(vec![512, 512, 512, 512]));
+        sub_ifd.set(0x787f, Value::Short(vec![16000, 16300, 16000]));
+        sub_ifd.set(WB_RGGB_LEVELS, Value::Short(vec![2048, 1024, 1024, 1536]));
+        let mut private = TiffIfd::new();
+        private.set(sr2::SR2_SUB_IFD_OFFSET, Value::Enciphered(Box::new(sub_ifd), 0x1234));
+        private.set(sr2::SR2_SUB_IFD_LENGTH, Value::Long(vec![0]));
+        private.set(sr2::SR2_SUB_IFD_KEY, Value::Long(vec![0x1234]));
+        let mut ifd0 = TiffIfd::new();
+        ifd0.set(MAKE, Value::Ascii(String::from("SONY")));
+        ifd0.set(MODEL, Value::Ascii(String::from("ILCE-7M3")));
+        ifd0.set(256, Value::Long(vec![2]));
+        ifd0.set(257, Value::Long(vec![2]));
+        ifd0.set(259, Value::Short(vec![1]));
+        ifd0.set(262, Value::Short(vec![32803]));
+        ifd0.set(CFA_PATTERN, Value::Byte(vec![1, 0, 2, 1]));
+        ifd0.set(273, Value::Offsets(vec![vec![1, 2, 3, 4, 5, 6, 7, 8]]));
+        ifd0.set(279, Value::Long(vec![8]));
+        ifd0.set(sr2::DNG_PRIVATE_DATA, Value::Ifds(vec![private]));
+        let mut f = Cursor::new(writer::encode(ByteOrder::LittleEndian, &[ifd0]));
+        let header = Header::new(&mut f).unwrap();
+
+        let mut dng = Cursor::new(dng_bytes(&mut f, &header).unwrap());
+        let header = Header::new(&mut dng).unwrap();
+        let entry = |tag_id| header.entry("Main", tag_id).unwrap();
+        assert_eq!(entry(50706).value_bytes, vec![1, 4, 0, 0]);
+        assert_eq!(ascii(entry(50708)), "SONY ILCE-7M3");
+        assert_eq!(entry(CFA_PATTERN).value_bytes, vec![1, 0, 2, 1]);
+        assert_eq!(entry(50714).unsigned_values(), Some(vec![512, 512, 512, 512]));
+        assert_eq!(entry(50717).unsigned_values(), Some(vec![16300]));
+        assert_eq!(entry(50713).short_values(), Some(vec![2, 2]));
+        assert_eq!(entry(50728).value_bytes.len(), 24);
+        let strip = entry(273).value_offset;
+        assert_eq!(read_at(&mut dng, strip as u64, 8).unwrap(), vec![1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+}
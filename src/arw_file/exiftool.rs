@@ -0,0 +1,272 @@
+// exiftool compatible output: family 1 group names (IFD0, ExifIFD, GPS, Sony,
+// SR2SubIFD..), exiftool tag names, `-G1 -s` lines and `-X` RDF/XML
+use arw_file::csv;
+use arw_file::filter::Filter;
+use arw_file::header::Header;
+use arw_file::ifd::{IFD, IFDEntry};
+
+pub const MAKER_NOTE: u16 = 37500;
+// Values longer than this, or than 16 bytes for UNDEFINED, are summarized
+// as exiftool does without -b
+const MAX_VALUE_BYTES: usize = 64;
+
+// exiftool names of tags whose label in TAGS differs
+const NAMES: [(u16, &str); 29] = [(306, "ModifyDate"),
+                                  (330, "SubIFD"),
+                                  (513, "ThumbnailOffset"),
+                                  (514, "ThumbnailLength"),
+                                  (700, "ApplicationNotes"),
+                                  (33723, "IPTC-NAA"),
+                                  (34665, "ExifOffset"),
+                                  (34675, "ICC_Profile"),
+                                  (34853, "GPSInfo"),
+                                  (34855, "ISO"),
+                                  (34864, "SensitivityType"),
+                                  (34866, "RecommendedExposureIndex"),
+                                  (36868, "CreateDate"),
+                                  (37380, "ExposureCompensation"),
+                                  (40962, "ExifImageWidth"),
+                                  (40963, "ExifImageHeight"),
+                                  (40965, "InteropOffset"),
+                                  (41989, "FocalLengthIn35mmFormat"),
+                                  (42032, "OwnerName"),
+                                  (42033, "SerialNumber"),
+                                  (42034, "LensInfo"),
+                                  (42035, "LensMake"),
+                                  (42036, "LensModel"),
+                                  (0x7303, "WB_GRBGLevels"),
+                                  (0x7310, "BlackLevel"),
+                                  (0x7313, "WB_RGGBLevels"),
+                                  (0x787f, "WhiteLevel"),
+                                  (50735, "CameraSerialNumber"),
+                                  (50740, "DNGPrivateData")];
+
+// Interoperability IFD tags, whose ids TAGS holds as GPS ones
+const INTEROP_NAMES: [(u16, &str); 4] = [(1, "InteropIndex"),
+                                         (2, "InteropVersion"),
+                                         (4097, "RelatedImageWidth"),
+                                         (4098, "RelatedImageHeight")];
+
+// Tags of the SR2 IFDs, whose ids mean nothing in TAGS
+const SR2_NAMES: [(u16, &str); 7] = [(0x7200, "SR2SubIFDOffset"),
+                                     (0x7201, "SR2SubIFDLength"),
+                                     (0x7221, "SR2SubIFDKey"),
+                                     (0x7303, "WB_GRBGLevels"),
+                                     (0x7310, "BlackLevel"),
+                                     (0x7313, "WB_RGGBLevels"),
+                                     (0x787f, "WhiteLevel")];
+
+// Family 1 group name of each IFD, in parse order. IFD0, IFD1.. follow the
+// main chain and SubIFD, SubIFD1.. the SubIFDs.
+pub fn groups<'a>(header: &'a Header, sr2_ifds: &'a [IFD]) -> Vec<(String, &'a IFD)> {
+    let (mut main, mut sub) = (0, 0);
+    header.ifds
+        .iter()
+        .chain(sr2_ifds.iter())
+        .map(|ifd| {
+            let group = match ifd.ifd_type.as_ref() {
+                "Main" => {
+                    main += 1;
+                    format!("IFD{}", main - 1)
+                }
+                "SubIFDs" => {
+                    sub += 1;
+                    if sub == 1 { String::from("SubIFD") } else { format!("SubIFD{}", sub - 1) }
+                }
+                "Exif IFD" => String::from("ExifIFD"),
+                "GPS IFD" => String::from("GPS"),
+                "Interoperability IFD" => String::from("InteropIFD"),
+                "MakerNote" => String::from("Sony"),
+                other => other.replace(' ', ""),
+            };
+            (group, ifd)
+        })
+        .collect()
+}
+
+pub fn is_maker_notes(group: &str) -> bool {
+    group == "Sony" || group.starts_with("SR2")
+}
+
+pub fn tag_name(group: &str, entry: &IFDEntry) -> String {
+    let id = entry.tag.id;
+    let find = |names: &[(u16, &str)]| {
+        names.iter().find(|&&(tag_id, _)| tag_id == id).map(|&(_, name)| String::from(name))
+    };
+    let name = match group {
+        "InteropIFD" => find(&INTEROP_NAMES),
+        group if group.starts_with("SR2") => find(&SR2_NAMES),
+        "Sony" => None,
+        _ => find(&NAMES),
+    };
+    // Sony labels list the names a tag goes by on different bodies, as in
+    // "AFInfo / Tag940e": the first one, which must make an XML name
+    let first = entry.tag.label.split(" / ").next().unwrap_or("");
+    let label = Some(first.chars().filter(|c| c.is_ascii_alphanumeric() || *c == '_').collect())
+        .filter(|label: &String| !label.is_empty())
+        .filter(|label| !label.starts_with("Unknowntag") && !group.starts_with("SR2") &&
+                        group != "InteropIFD");
+    name.or(label).unwrap_or_else(|| format!("{}_0x{:04x}", group, id))
+}
+
+pub fn value(entry: &IFDEntry) -> String {
+    let bytes = &entry.value_bytes;
+    let text = bytes.iter().all(|b| (0x20..0x7f).contains(b) || *b == 0);
+    if entry.field_type.name() == "UNDEFINED" && text {
+        return String::from_utf8_lossy(bytes).trim_end_matches('\0').to_string();
+    }
+    let limit = if entry.field_type.name() == "UNDEFINED" { 16 } else { MAX_VALUE_BYTES };
+    if bytes.len() > limit && entry.field_type.name() != "ASCII" {
+        return format!("(Binary data {} bytes, use -b option to extract)", bytes.len());
+    }
+    match entry.field_type.name() {
+        "UNDEFINED" => bytes.iter().map(|b| b.to_string()).collect::<Vec<String>>().join(" "),
+        _ => csv::value(entry),
+    }
+}
+
+// (group, name, value) of each entry, the MakerNote block left out as its
+// tags are listed under Sony
+pub fn tags(groups: &[(String, &IFD)], filter: &Filter) -> Vec<(String, String, String)> {
+    let mut tags = vec![];
+    for (group, ifd) in groups {
+        let entries = ifd.entries
+            .iter()
+            .filter(|entry| entry.tag.id != MAKER_NOTE)
+            .filter(|entry| filter.includes(group, &ifd.ifd_type, entry));
+        for entry in entries {
+            tags.push((group.clone(), tag_name(group, entry), value(entry)));
+        }
+    }
+    tags
+}
+
+// "[ExifIFD]       ExposureTime                    : 1/250", as -G1 -s prints
+pub fn line(group: Option<&str>, name: &str, value: &str) -> String {
+    match group {
+        Some(group) => format!("{:<15} {:<32}: {}", format!("[{}]", group), name, value),
+        None => format!("{:<32}: {}", name, value),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('\'', "&#39;")
+}
+
+pub const RDF_HEADER: &str = "<?xml version='1.0' encoding='UTF-8'?>\n<rdf:RDF \
+                               xmlns:rdf='http://www.w3.org/1999/02/22-rdf-syntax-ns#'>\n";
+pub const RDF_FOOTER: &str = "</rdf:RDF>\n";
+
+// The rdf:Description of one file, one namespace per group
+pub fn rdf_description(filename: &str, tags: &[(String, String, String)]) -> String {
+    let mut xml = format!("\n<rdf:Description rdf:about='{}'\n  \
+                           xmlns:et='http://ns.exiftool.org/1.0/' et:toolkit='arw_info {}'",
+                          escape(filename),
+                          env!("CARGO_PKG_VERSION"));
+    let mut groups: Vec<&str> = vec![];
+    for (group, _, _) in tags {
+        if !groups.contains(&group.as_ref()) {
+            groups.push(group);
+        }
+    }
+    for group in &groups {
+        let family = if is_maker_notes(group) { "MakerNotes" } else { "EXIF" };
+        xml.push_str(&format!("\n  xmlns:{}='http://ns.exiftool.org/{}/{}/1.0/'",
+                              group,
+                              family,
+                              group));
+    }
+    xml.push_str(">\n");
+    for (group, name, value) in tags {
+        xml.push_str(&format!(" <{}:{}>{}</{}:{}>\n", group, name, escape(value), group, name));
+    }
+    xml.push_str("</rdf:Description>\n");
+    xml
+}
+
+// RDF/XML as -X prints it
+pub fn rdf(filename: &str, tags: &[(String, String, String)]) -> String {
+    format!("{}{}{}", RDF_HEADER, rdf_description(filename, tags), RDF_FOOTER)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arw_file::byte_order::ByteOrder;
+    use arw_file::writer::{self, TiffIfd, Value};
+    use std::io::Cursor;
+
+    fn header() -> Header {
+        let mut exif = TiffIfd::new();
+        exif.set(33434, Value::Rational(vec![(1, 250)]));
+        exif.set(34855, Value::Short(vec![100]));
+        exif.set(36864, Value::Undefined(b"0230".to_vec()));
+        exif.set(42036, Value::Ascii(String::from("FE 24-70mm F2.8 GM")));
+        let mut ifd0 = TiffIfd::new();
+        ifd0.set(271, Value::Ascii(String::from("SONY")));
+        ifd0.set(34665, Value::Ifds(vec![exif]));
+        let ifd1 = TiffIfd::new();
+        let mut f = Cursor::new(writer::encode(ByteOrder::LittleEndian, &[ifd0, ifd1]));
+        Header::new(&mut f).unwrap()
+    }
+
+    #[test]
+    fn test_short_lines() {
+        let header = header();
+        let groups = groups(&header, &[]);
+        let names: Vec<&str> = groups.iter().map(|g| g.0.as_ref()).collect();
+        assert_eq!(names, vec!["IFD0", "ExifIFD", "IFD1"]);
+
+        let lines: Vec<String> = tags(&groups, &Filter::default())
+            .iter()
+            .map(|(group, name, value)| line(Some(group), name, value))
+            .collect();
+        assert_eq!(lines[0], "[IFD0]          Make                            : SONY");
+        assert!(lines[1].starts_with("[IFD0]          ExifOffset "));
+        assert_eq!(lines[2], "[ExifIFD]       ExposureTime                    : 1/250");
+        assert_eq!(lines[3], "[ExifIFD]       ISO                             : 100");
+        assert_eq!(lines[4], "[ExifIFD]       ExifVersion                     : 0230");
+        assert_eq!(lines[5],
+                   "[ExifIFD]       LensModel                       : FE 24-70mm F2.8 GM");
+    }
+
+    #[test]
+    fn test_rdf() {
+        let header = header();
+        let xml = rdf("a&b.arw", &tags(&groups(&header, &[]), &Filter::default()));
+        assert!(xml.contains("<rdf:Description rdf:about='a&amp;b.arw'"));
+        assert!(xml.contains("\n  xmlns:ExifIFD='http://ns.exiftool.org/EXIF/ExifIFD/1.0/'"));
+        assert!(xml.contains("\n <ExifIFD:ISO>100</ExifIFD:ISO>\n"));
+        assert!(xml.ends_with("</rdf:Description>\n</rdf:RDF>\n"));
+    }
+
+    #[test]
+    fn test_sr2_names() {
+        let mut sub_ifd = TiffIfd::new();
+        sub_ifd.set(0x7310, Value::Short(vec![512, 512, 512, 512]));
+        sub_ifd.set(0x787f, Value::Short(vec![16383, 16383, 16383]));
+        let mut f = Cursor::new(writer::encode(ByteOrder::LittleEndian, &[sub_ifd]));
+        let header = Header::new(&mut f).unwrap();
+        let names: Vec<String> = header.ifds[0].entries
+            .iter()
+            .map(|entry| tag_name("SR2SubIFD", entry))
+            .collect();
+        assert_eq!(names, vec!["BlackLevel", "WhiteLevel"]);
+    }
+
+    #[test]
+    fn test_sony_names() {
+        let mut sony = TiffIfd::new();
+        sony.set(0x940e, Value::Undefined(vec![1, 2, 3, 4, 5, 6, 7, 8]));
+        let mut exif = TiffIfd::new();
+        exif.set(MAKER_NOTE, Value::MakerNote(vec![], Box::new(sony)));
+        let mut ifd0 = TiffIfd::new();
+        ifd0.set(34665, Value::Ifds(vec![exif]));
+        let mut f = Cursor::new(writer::encode(ByteOrder::LittleEndian, &[ifd0]));
+        let header = Header::new(&mut f).unwrap();
+        let tags = tags(&groups(&header, &[]), &Filter::default());
+        assert!(tags.contains(&(String::from("Sony"), String::from("AFInfo"),
+                                String::from("1 2 3 4 5 6 7 8"))));
+        assert!(rdf("a.arw", &tags).contains("\n <Sony:AFInfo>1 2 3 4 5 6 7 8</Sony:AFInfo>\n"));
+    }
+}
@@ -0,0 +1,232 @@
+// Undemosaiced sensor data export, for tools expecting dcraw -D -4 style output
+use num::ToPrimitive;
+
+use arw_file::byte_order::ByteOrder;
+use arw_file::header::Header;
+use arw_file::ifd::{IFD, IFDEntry};
+use arw_file::raw::{CfaImage, RawIfd};
+use arw_file::writer::{self, TiffIfd, Value};
+
+const BLACK_LEVEL: u16 = 50714;
+const WHITE_LEVEL: u16 = 50717;
+const DEFAULT_CROP_ORIGIN: u16 = 50719;
+const DEFAULT_CROP_SIZE: u16 = 50720;
+// Sony raw IFD
+const SONY_BLACK_LEVEL: u16 = 0x7310;
+const SONY_WHITE_LEVEL: u16 = 0x787f;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum RawFormat {
+    Pgm,
+    Tiff,
+}
+
+impl RawFormat {
+    pub fn from_name(name: &str) -> Option<RawFormat> {
+        match name.to_lowercase().as_ref() {
+            "pgm" => Some(RawFormat::Pgm),
+            "tif" | "tiff" => Some(RawFormat::Tiff),
+            _ => None,
+        }
+    }
+}
+
+pub struct Levels {
+    // One value per CFA position, or a single value for all
+    pub black: Vec<u32>,
+    pub white: Option<u32>,
+}
+
+impl Levels {
+    // Sony ARW files keep the levels in the SR2SubIFD, looked at first
+    pub fn new(header: &Header, raw: &RawIfd, sr2_sub_ifd: Option<&IFD>) -> Levels {
+        let black = raw_ifd_values(header, raw, sr2_sub_ifd, &[BLACK_LEVEL, SONY_BLACK_LEVEL])
+            .unwrap_or_default();
+        let white = raw_ifd_values(header, raw, sr2_sub_ifd, &[WHITE_LEVEL, SONY_WHITE_LEVEL])
+            .and_then(|values| values.into_iter().max());
+        Levels { black, white }
+    }
+
+    pub fn describe(&self) -> String {
+        let black: Vec<String> = self.black.iter().map(|b| b.to_string()).collect();
+        format!("black level: {}, white level: {}",
+                if black.is_empty() { String::from("unknown") } else { black.join(" ") },
+                self.white.map_or(String::from("unknown"), |w| w.to_string()))
+    }
+}
+
+// SHORT, LONG or RATIONAL values as integers
+fn integer_values(entry: &IFDEntry) -> Option<Vec<u32>> {
+    entry.unsigned_values().or_else(|| {
+        entry.rational_values()
+            .map(|values| {
+                values.iter()
+                    .filter(|r| *r.denom() != 0)
+                    .filter_map(|r| r.to_integer().to_u32())
+                    .collect()
+            })
+    })
+}
+
+// First of the tags present in the SR2SubIFD or the raw IFD, falling back to
+// the other IFDs
+fn raw_ifd_values(header: &Header,
+                  raw: &RawIfd,
+                  sr2_sub_ifd: Option<&IFD>,
+                  tag_ids: &[u16])
+                  -> Option<Vec<u32>> {
+    let ifds = sr2_sub_ifd.into_iter()
+        .chain(Some(raw.ifd))
+        .chain(header.ifds.iter().filter(|ifd| ifd.ifd_type != "MakerNote"));
+    for ifd in ifds {
+        for tag_id in tag_ids {
+            if let Some(values) = ifd.entry(*tag_id).and_then(integer_values) {
+                return Some(values);
+            }
+        }
+    }
+    None
+}
+
+// (x, y, width, height) from DefaultCropOrigin and DefaultCropSize
+pub fn crop_area(raw: &RawIfd) -> Option<(u32, u32, u32, u32)> {
+    let origin = raw.ifd.entry(DEFAULT_CROP_ORIGIN).and_then(integer_values);
+    let size = raw.ifd.entry(DEFAULT_CROP_SIZE).and_then(integer_values);
+    match (origin, size) {
+        (Some(origin), Some(size)) if origin.len() == 2 && size.len() == 2 => {
+            Some((origin[0], origin[1], size[0], size[1]))
+        }
+        _ => None,
+    }
+}
+
+pub fn crop(image: &CfaImage, area: (u32, u32, u32, u32)) -> CfaImage {
+    let (x, y, width, height) = area;
+    let x = x.min(image.width);
+    let y = y.min(image.height);
+    let width = width.min(image.width - x);
+    let height = height.min(image.height - y);
+
+    let mut data = Vec::with_capacity((width * height) as usize);
+    for row in y..y + height {
+        let start = (row * image.width + x) as usize;
+        data.extend_from_slice(&image.data[start..start + width as usize]);
+    }
+    CfaImage { width, height, data }
+}
+
+// Binary PGM, 16 bit big endian samples, levels in a header comment
+pub fn pgm_bytes(image: &CfaImage, levels: &Levels) -> Vec<u8> {
+    let mut bytes = format!("P5\n# {}\n{} {}\n65535\n",
+                            levels.describe(),
+                            image.width,
+                            image.height)
+        .into_bytes();
+    bytes.reserve(image.data.len() * 2);
+    for sample in &image.data {
+        bytes.extend_from_slice(&ByteOrder::BigEndian.u16_to_slice(*sample));
+    }
+    bytes
+}
+
+// Single channel, linear, uncompressed 16 bit TIFF, levels in the DNG tags
+pub fn tiff_bytes(image: &CfaImage, levels: &Levels) -> Vec<u8> {
+    let byte_order = ByteOrder::LittleEndian;
+    let mut strip = Vec::with_capacity(image.data.len() * 2);
+    for sample in &image.data {
+        strip.extend_from_slice(&byte_order.u16_to_slice(*sample));
+    }
+
+    let mut ifd = TiffIfd::new();
+    ifd.set(254, Value::Long(vec![0]));
+    ifd.set(256, Value::Long(vec![image.width]));
+    ifd.set(257, Value::Long(vec![image.height]));
+    ifd.set(258, Value::Short(vec![16]));
+    ifd.set(259, Value::Short(vec![1]));
+    // BlackIsZero
+    ifd.set(262, Value::Short(vec![1]));
+    ifd.set(270, Value::Ascii(levels.describe()));
+    ifd.set(277, Value::Short(vec![1]));
+    ifd.set(278, Value::Long(vec![image.height]));
+    ifd.set(279, Value::Long(vec![strip.len() as u32]));
+    ifd.set(284, Value::Short(vec![1]));
+    ifd.set(305, Value::Ascii(format!("arw_info {}", env!("CARGO_PKG_VERSION"))));
+    if !levels.black.is_empty() {
+        ifd.set(BLACK_LEVEL, Value::Long(levels.black.clone()));
+    }
+    if let Some(white) = levels.white {
+        ifd.set(WHITE_LEVEL, Value::Long(vec![white]));
+    }
+    ifd.set(273, Value::Offsets(vec![strip]));
+
+    writer::encode(byte_order, &[ifd])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_crop() {
+        let image = CfaImage {
+            width: 4,
+            height: 3,
+            data: (0..12).collect(),
+        };
+        let cropped = crop(&image, (1, 1, 2, 5));
+        assert_eq!((cropped.width, cropped.height), (2, 2));
+        assert_eq!(cropped.data, vec![5, 6, 9, 10]);
+    }
+
+    #[test]
+    fn test_levels_from_sr2_sub_ifd() {
+        let mut raw = TiffIfd::new();
+        raw.set(256, Value::Long(vec![2]));
+        raw.set(257, Value::Long(vec![1]));
+        raw.set(262, Value::Short(vec![32803]));
+        raw.set(BLACK_LEVEL, Value::Long(vec![0]));
+        let mut sr2 = TiffIfd::new();
+        sr2.set(SONY_BLACK_LEVEL, Value::Short(vec![512, 512, 512, 512]));
+        sr2.set(SONY_WHITE_LEVEL, Value::Short(vec![16000, 16300, 16000]));
+        let encode = |ifd| writer::encode(ByteOrder::LittleEndian, &[ifd]);
+        let header = Header::new(&mut Cursor::new(encode(raw))).unwrap();
+        let sr2_header = Header::new(&mut Cursor::new(encode(sr2))).unwrap();
+        let raw_ifd = RawIfd::locate(&header).unwrap();
+
+        let levels = Levels::new(&header, &raw_ifd, Some(&sr2_header.ifds[0]));
+        assert_eq!(levels.black, vec![512, 512, 512, 512]);
+        assert_eq!(levels.white, Some(16300));
+        let levels = Levels::new(&header, &raw_ifd, None);
+        assert_eq!(levels.black, vec![0]);
+        assert_eq!(levels.white, None);
+    }
+
+    #[test]
+    fn test_zero_denominator() {
+        let mut ifd = TiffIfd::new();
+        ifd.set(BLACK_LEVEL, Value::Rational(vec![(0, 0), (512, 1), (7, 0)]));
+        let bytes = writer::encode(ByteOrder::LittleEndian, &[ifd]);
+        let header = Header::new(&mut Cursor::new(bytes)).unwrap();
+        let entry = header.entry("Main", BLACK_LEVEL).unwrap();
+        assert_eq!(integer_values(entry), Some(vec![512]));
+        assert_eq!(entry.string_value(), "[0/0, 512, 7/0]");
+    }
+
+    #[test]
+    fn test_pgm_bytes() {
+        let image = CfaImage {
+            width: 2,
+            height: 1,
+            data: vec![1, 0x200],
+        };
+        let levels = Levels {
+            black: vec![512],
+            white: Some(16383),
+        };
+        let pgm = pgm_bytes(&image, &levels);
+        let header = "P5\n# black level: 512, white level: 16383\n2 1\n65535\n";
+        assert_eq!(&pgm[..header.len()], header.as_bytes());
+        assert_eq!(&pgm[header.len()..], &[0, 1, 2, 0]);
+    }
+}
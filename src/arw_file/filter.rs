@@ -0,0 +1,106 @@
+// Selection of the IFDs and entries to print, from --tag, --ifd and --exclude.
+// Tags are given by id, label or exiftool name, with * and ? wildcards;
+// IFDs by type ("Exif IFD"), first word ("Exif") or exiftool group ("IFD1").
+use arw_file::exiftool;
+use arw_file::ifd::tag;
+use arw_file::ifd::IFDEntry;
+
+#[derive(Default)]
+pub struct Filter {
+    pub tags: Vec<String>,
+    pub ifds: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+// Case insensitive match of `text` against a pattern with * and ?
+pub fn wildcard(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    // Position after the last *, and the text position it was tried at
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p + 1, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p;
+            t = star_t + 1;
+            star = Some((star_p, t));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+pub fn ifd_matches(spec: &str, group: &str, ifd_type: &str) -> bool {
+    let first_word = ifd_type.split(' ').next().unwrap_or(ifd_type);
+    [ifd_type, first_word, group].iter().any(|name| name.eq_ignore_ascii_case(spec))
+}
+
+// By id, label of the TIFF or Sony tags, or a wildcard over `names`
+fn tag_matches(spec: &str, sony: bool, id: u16, names: &[&str]) -> bool {
+    tag::parse_id(spec, sony) == Some(id) || names.iter().any(|name| wildcard(spec, name))
+}
+
+impl Filter {
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.ifds.is_empty() && self.exclude.is_empty()
+    }
+
+    pub fn includes_ifd(&self, group: &str, ifd_type: &str) -> bool {
+        self.ifds.is_empty() || self.ifds.iter().any(|spec| ifd_matches(spec, group, ifd_type))
+    }
+
+    // Whether `entry` of an IFD in `group` is printed, the IFD filter aside
+    pub fn includes_entry(&self, group: &str, entry: &IFDEntry) -> bool {
+        let exiftool_name = exiftool::tag_name(group, entry);
+        let names = [entry.tag.label.as_ref(), exiftool_name.as_ref()];
+        let (sony, id) = (exiftool::is_maker_notes(group), entry.tag.id);
+        let matches = |spec: &String| tag_matches(spec, sony, id, &names);
+        (self.tags.is_empty() || self.tags.iter().any(matches)) &&
+        !self.exclude.iter().any(matches)
+    }
+
+    pub fn includes(&self, group: &str, ifd_type: &str, entry: &IFDEntry) -> bool {
+        self.includes_ifd(group, ifd_type) && self.includes_entry(group, entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildcard() {
+        assert!(wildcard("Exposure*", "ExposureTime"));
+        assert!(wildcard("*time", "ExposureTime"));
+        assert!(wildcard("f?umber", "FNumber"));
+        assert!(wildcard("*a*b*", "xaxxbx"));
+        assert!(wildcard("*", ""));
+        assert!(!wildcard("Exposure", "ExposureTime"));
+        assert!(!wildcard("*Bias", "ExposureBiasValue"));
+    }
+
+    #[test]
+    fn test_ifd_matches() {
+        assert!(ifd_matches("Exif", "ExifIFD", "Exif IFD"));
+        assert!(ifd_matches("gps", "GPS", "GPS IFD"));
+        assert!(ifd_matches("IFD1", "IFD1", "Main"));
+        assert!(!ifd_matches("IFD1", "IFD0", "Main"));
+        assert!(ifd_matches("MakerNote", "Sony", "MakerNote"));
+    }
+
+    #[test]
+    fn test_tag_matches() {
+        assert!(tag_matches("0x829a", false, 33434, &["ExposureTime"]));
+        assert!(tag_matches("33434", false, 33434, &["ExposureTime"]));
+        assert!(tag_matches("iso", false, 34855, &["ISOSpeedRatings", "ISO"]));
+        assert!(!tag_matches("ISO", false, 34855, &["ISOSpeedRatings"]));
+        assert!(tag_matches("FNumber", false, 33437, &[]));
+    }
+}
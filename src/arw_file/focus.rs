@@ -0,0 +1,432 @@
+use std::fmt;
+use std::io::{self, Write};
+
+use arw_file::header::Header;
+
+const MODEL: u16 = 272;
+const IMAGE_WIDTH: u16 = 256;
+const IMAGE_LENGTH: u16 = 257;
+const PIXEL_X_DIMENSION: u16 = 40962;
+const PIXEL_Y_DIMENSION: u16 = 40963;
+
+// Sony MakerNote tags
+const AF_AREA_MODE_SETTING: u16 = 0x201c;
+const FLEXIBLE_SPOT_POSITION: u16 = 0x201d;
+const AF_POINT_SELECTED: u16 = 0x201e;
+const AF_POINTS_USED: u16 = 0x2020;
+const FOCAL_PLANE_AF_POINTS_USED: u16 = 0x2022;
+const FOCUS_LOCATION: u16 = 0x2027;
+const AF_INFO: u16 = 0x940e;
+const FULL_IMAGE_SIZE: u16 = 0xb02b;
+const PREVIEW_IMAGE_SIZE: u16 = 0xb02c;
+
+// Source of the focal plane points, bit indices into a grid that differs per
+// body and is not recorded, so they have no position
+const FOCAL_PLANE_SOURCE: &str = "FocalPlaneAFPointsUsed";
+
+// FlexibleSpotPosition is relative to a 640x480 frame centered on the image
+const FLEXIBLE_SPOT_FRAME: (u32, u32) = (640, 480);
+
+// AFAreaModeSetting value of NEX/ILCE bodies for Zone
+const ILCE_AF_AREA_ZONE: u32 = 11;
+
+// Phase detection points of SLT/ILCA bodies (15 and 19 point sensors),
+// indexed by AFPointsUsed bit, or by AFPointSelected - 1
+const AF_POINTS_19: [&str; 19] = ["Center",
+                                  "Top",
+                                  "Upper-right",
+                                  "Right",
+                                  "Lower-right",
+                                  "Bottom",
+                                  "Lower-left",
+                                  "Left",
+                                  "Upper-left",
+                                  "Far Right",
+                                  "Far Left",
+                                  "Upper-middle",
+                                  "Near Right",
+                                  "Lower-middle",
+                                  "Near Left",
+                                  "Upper Far Right",
+                                  "Lower Far Right",
+                                  "Lower Far Left",
+                                  "Upper Far Left"];
+
+// AFPointSelected of NEX/ILCE bodies in Zone mode, indexed by value - 1
+const AF_ZONES: [&str; 9] = ["Center Zone",
+                             "Top Zone",
+                             "Right Zone",
+                             "Left Zone",
+                             "Bottom Zone",
+                             "Bottom Right Zone",
+                             "Bottom Left Zone",
+                             "Top Left Zone",
+                             "Top Right Zone"];
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum AFSystem {
+    // SLT and ILCA bodies, dedicated phase detection sensor
+    PhaseDetect,
+    // NEX, ILCE and DSC bodies, on-sensor AF
+    OnSensor,
+}
+
+impl AFSystem {
+    pub fn for_model(model: &str) -> AFSystem {
+        if model.starts_with("SLT-") || model.starts_with("ILCA-") || model.starts_with("DSLR-") {
+            AFSystem::PhaseDetect
+        } else {
+            AFSystem::OnSensor
+        }
+    }
+}
+
+pub struct FocusPoint {
+    pub name: String,
+    pub source: String,
+    // Position in full image pixels, when the tag carries one
+    pub position: Option<(u32, u32)>,
+}
+
+impl fmt::Display for FocusPoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.position {
+            Some((x, y)) => write!(f, "{} at {},{} ({})", self.name, x, y, self.source),
+            None => write!(f, "{} ({})", self.name, self.source),
+        }
+    }
+}
+
+pub struct FocusInfo {
+    pub model: Option<String>,
+    pub af_system: AFSystem,
+    pub af_type: Option<String>,
+    // (width, height)
+    pub image_size: Option<(u32, u32)>,
+    pub preview_size: Option<(u32, u32)>,
+    pub points: Vec<FocusPoint>,
+}
+
+impl FocusInfo {
+    pub fn new(header: &Header) -> FocusInfo {
+        let model = header.entry("Main", MODEL)
+            .and_then(|entry| entry.ascii_value())
+            .map(|model| String::from(model.trim_end_matches('\0').trim()));
+        let af_system = AFSystem::for_model(model.as_ref().map_or("", |m| m.as_ref()));
+        let makernote_values = |tag_id| {
+            header.entry("MakerNote", tag_id).and_then(|entry| entry.unsigned_values())
+        };
+
+        let image_size = makernote_values(FULL_IMAGE_SIZE)
+            .and_then(|v| height_width(&v))
+            .or_else(|| exif_image_size(header));
+        let preview_size = makernote_values(PREVIEW_IMAGE_SIZE).and_then(|v| height_width(&v));
+
+        let mut points = vec![];
+
+        if let Some(selected) = makernote_values(AF_POINT_SELECTED).and_then(|v| first(&v)) {
+            let area_mode = makernote_values(AF_AREA_MODE_SETTING).and_then(|v| first(&v));
+            let name = match af_system {
+                AFSystem::PhaseDetect if selected == 0 => Some("Auto"),
+                AFSystem::PhaseDetect => point_name(&AF_POINTS_19, selected - 1),
+                AFSystem::OnSensor if area_mode == Some(ILCE_AF_AREA_ZONE) && selected > 0 => {
+                    point_name(&AF_ZONES, selected - 1)
+                }
+                AFSystem::OnSensor => None,
+            };
+            if let Some(name) = name {
+                points.push(FocusPoint {
+                    name: String::from(name),
+                    source: String::from("AFPointSelected"),
+                    position: None,
+                });
+            }
+        }
+
+        if af_system == AFSystem::PhaseDetect {
+            if let Some(used) = header.entry("MakerNote", AF_POINTS_USED) {
+                for bit in set_bits(&used.value_bytes) {
+                    points.push(FocusPoint {
+                        name: point_name(&AF_POINTS_19, bit as u32)
+                            .map_or(format!("Point {}", bit), String::from),
+                        source: String::from("AFPointsUsed"),
+                        position: None,
+                    });
+                }
+            }
+        }
+
+        if let Some(used) = header.entry("MakerNote", FOCAL_PLANE_AF_POINTS_USED) {
+            for bit in set_bits(&used.value_bytes) {
+                points.push(FocusPoint {
+                    name: format!("Focal plane point {}", bit),
+                    source: String::from(FOCAL_PLANE_SOURCE),
+                    position: None,
+                });
+            }
+        }
+
+        if let (Some(spot), Some((width, height))) = (makernote_values(FLEXIBLE_SPOT_POSITION),
+                                                      image_size) {
+            if spot.len() == 2 {
+                points.push(FocusPoint {
+                    name: String::from("Flexible Spot"),
+                    source: String::from("FlexibleSpotPosition"),
+                    position: Some((scale(spot[0], FLEXIBLE_SPOT_FRAME.0, width),
+                                    scale(spot[1], FLEXIBLE_SPOT_FRAME.1, height))),
+                });
+            }
+        }
+
+        if let Some(location) = makernote_values(FOCUS_LOCATION) {
+            // width, height, x, y; all zeros when not recorded
+            if location.len() == 4 && location[0] > 0 && location[1] > 0 {
+                let position = match image_size {
+                    Some((width, height)) => {
+                        (scale(location[2], location[0], width),
+                         scale(location[3], location[1], height))
+                    }
+                    None => (location[2], location[3]),
+                };
+                points.push(FocusPoint {
+                    name: String::from("Focus Location"),
+                    source: String::from("FocusLocation"),
+                    position: Some(position),
+                });
+            }
+        }
+
+        let af_type = header.entry("MakerNote", AF_INFO)
+            .map(|entry| decipher(&entry.value_bytes))
+            .and_then(|af_info| af_info.get(2).cloned())
+            .and_then(af_type_name)
+            .map(String::from);
+
+        FocusInfo {
+            model,
+            af_system,
+            af_type,
+            image_size,
+            preview_size,
+            points,
+        }
+    }
+
+    // Position of a focus point scaled to the embedded preview
+    pub fn preview_position(&self, point: &FocusPoint) -> Option<(u32, u32)> {
+        match (point.position, self.image_size, self.preview_size) {
+            (Some((x, y)), Some((width, height)), Some((p_width, p_height))) => {
+                Some((scale(x, width, p_width), scale(y, height, p_height)))
+            }
+            _ => None,
+        }
+    }
+}
+
+// Sony enciphers some MakerNote blocks by cubing each byte modulo 249
+pub fn decipher(bytes: &[u8]) -> Vec<u8> {
+    let mut table: Vec<u8> = (0..256).map(|b| b as u8).collect();
+    for b in 0..249u32 {
+        table[((b * b * b) % 249) as usize] = b as u8;
+    }
+    bytes.iter().map(|b| table[*b as usize]).collect()
+}
+
+fn af_type_name(value: u8) -> Option<&'static str> {
+    match value {
+        2 => Some("15-point"),
+        6 => Some("19-point"),
+        11 => Some("79-point"),
+        _ => None,
+    }
+}
+
+fn exif_image_size(header: &Header) -> Option<(u32, u32)> {
+    let exif_values = |tag_id| {
+        header.entry("Exif IFD", tag_id)
+            .and_then(|entry| entry.unsigned_values())
+            .and_then(|v| first(&v))
+    };
+    if let (Some(width), Some(height)) = (exif_values(PIXEL_X_DIMENSION),
+                                          exif_values(PIXEL_Y_DIMENSION)) {
+        return Some((width, height));
+    }
+
+    // Fall back to the largest image described by the IFD chain
+    header.ifds
+        .iter()
+        .filter_map(|ifd| {
+            let value = |tag_id| {
+                ifd.entry(tag_id).and_then(|e| e.unsigned_values()).and_then(|v| first(&v))
+            };
+            let (width, height) = (value(IMAGE_WIDTH), value(IMAGE_LENGTH));
+            match (width, height) {
+                (Some(width), Some(height)) => Some((width, height)),
+                _ => None,
+            }
+        })
+        .max_by_key(|&(width, height)| width as u64 * height as u64)
+}
+
+// Sony stores sizes as height, width
+fn height_width(values: &[u32]) -> Option<(u32, u32)> {
+    if values.len() == 2 && values[0] > 0 && values[1] > 0 {
+        Some((values[1], values[0]))
+    } else {
+        None
+    }
+}
+
+fn first(values: &[u32]) -> Option<u32> {
+    values.first().cloned()
+}
+
+fn point_name(names: &[&'static str], index: u32) -> Option<&'static str> {
+    names.get(index as usize).cloned()
+}
+
+fn set_bits(bytes: &[u8]) -> Vec<usize> {
+    let mut bits = vec![];
+    for (i, byte) in bytes.iter().enumerate() {
+        for bit in 0..8 {
+            if byte & (1 << bit) != 0 {
+                bits.push(i * 8 + bit);
+            }
+        }
+    }
+    bits
+}
+
+fn scale(value: u32, from: u32, to: u32) -> u32 {
+    if from == 0 {
+        return value;
+    }
+    (value as u64 * to as u64 / from as u64) as u32
+}
+
+pub fn pretty_print<W: Write>(out: &mut W, focus_info: &FocusInfo) -> io::Result<()> {
+    writeln!(out,
+             "\nFocus ({:?}{}{})",
+             focus_info.af_system,
+             focus_info.model.as_ref().map_or(String::new(), |m| format!(", {}", m)),
+             focus_info.af_type.as_ref().map_or(String::new(), |t| format!(", {}", t)))?;
+    if let Some((width, height)) = focus_info.image_size {
+        writeln!(out, "  Image size: {}x{}", width, height)?;
+    }
+    if let Some((width, height)) = focus_info.preview_size {
+        writeln!(out, "  Preview size: {}x{}", width, height)?;
+    }
+    if focus_info.points.is_empty() {
+        writeln!(out, "  No focus points recorded")?;
+    }
+    for point in &focus_info.points {
+        match focus_info.preview_position(point) {
+            Some((x, y)) => writeln!(out, "  {}, preview {},{}", point, x, y)?,
+            None => writeln!(out, "  {}", point)?,
+        }
+    }
+    if focus_info.points.iter().any(|point| point.source == FOCAL_PLANE_SOURCE) {
+        writeln!(out,
+                 "  Focal plane points are grid indices, their positions are not recorded \
+                  for this body")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arw_file::byte_order::ByteOrder;
+    use arw_file::writer::{self, TiffIfd, Value};
+    use std::io::Cursor;
+
+    fn header(model: &str, maker_note: TiffIfd) -> Header {
+        let mut exif = TiffIfd::new();
+        exif.set(37500, Value::MakerNote(vec![], Box::new(maker_note)));
+        let mut ifd0 = TiffIfd::new();
+        ifd0.set(MODEL, Value::Ascii(String::from(model)));
+        ifd0.set(34665, Value::Ifds(vec![exif]));
+        let mut f = Cursor::new(writer::encode(ByteOrder::LittleEndian, &[ifd0]));
+        Header::new(&mut f).unwrap()
+    }
+
+    fn names(focus_info: &FocusInfo) -> Vec<String> {
+        focus_info.points.iter().map(|point| point.to_string()).collect()
+    }
+
+    #[test]
+    fn test_phase_detect_points() {
+        let mut sony = TiffIfd::new();
+        sony.set(AF_POINT_SELECTED, Value::Short(vec![2]));
+        sony.set(AF_POINTS_USED, Value::Undefined(vec![0b11, 0, 0]));
+        sony.set(FOCUS_LOCATION, Value::Short(vec![600, 400, 300, 100]));
+        sony.set(FULL_IMAGE_SIZE, Value::Long(vec![4000, 6000]));
+        // AFType 6 enciphered: 6 ^ 3 % 249 = 216
+        sony.set(AF_INFO, Value::Undefined(vec![0, 0, 216, 0]));
+        let focus_info = FocusInfo::new(&header("SLT-A99V", sony));
+
+        assert_eq!(focus_info.af_system, AFSystem::PhaseDetect);
+        assert_eq!(focus_info.af_type, Some(String::from("19-point")));
+        assert_eq!(focus_info.image_size, Some((6000, 4000)));
+        assert_eq!(names(&focus_info),
+                   vec!["Top (AFPointSelected)",
+                        "Center (AFPointsUsed)",
+                        "Top (AFPointsUsed)",
+                        "Focus Location at 3000,1000 (FocusLocation)"]);
+    }
+
+    #[test]
+    fn test_on_sensor_points() {
+        let mut sony = TiffIfd::new();
+        sony.set(AF_AREA_MODE_SETTING, Value::Short(vec![ILCE_AF_AREA_ZONE as u16]));
+        sony.set(AF_POINT_SELECTED, Value::Short(vec![3]));
+        sony.set(FLEXIBLE_SPOT_POSITION, Value::Short(vec![320, 120]));
+        sony.set(FULL_IMAGE_SIZE, Value::Long(vec![4000, 6000]));
+        sony.set(PREVIEW_IMAGE_SIZE, Value::Long(vec![1080, 1620]));
+        let focus_info = FocusInfo::new(&header("ILCE-7M3", sony));
+
+        assert_eq!(focus_info.af_system, AFSystem::OnSensor);
+        assert_eq!(names(&focus_info),
+                   vec!["Right Zone (AFPointSelected)",
+                        "Flexible Spot at 3000,1000 (FlexibleSpotPosition)"]);
+        assert_eq!(focus_info.preview_position(&focus_info.points[1]), Some((810, 270)));
+    }
+
+    #[test]
+    fn test_decipher() {
+        // 2 ^ 3 = 8, 10 ^ 3 % 249 = 4
+        assert_eq!(decipher(&[8, 4, 0, 1, 250]), vec![2, 10, 0, 1, 250]);
+    }
+
+    #[test]
+    fn test_set_bits() {
+        assert_eq!(set_bits(&[0b101, 0, 0b10]), vec![0, 2, 17]);
+    }
+
+    #[test]
+    fn test_focal_plane_points() {
+        let focus_info = FocusInfo {
+            model: Some(String::from("ILCE-7M3")),
+            af_system: AFSystem::OnSensor,
+            af_type: None,
+            image_size: None,
+            preview_size: None,
+            points: vec![FocusPoint {
+                             name: String::from("Focal plane point 12"),
+                             source: String::from(FOCAL_PLANE_SOURCE),
+                             position: None,
+                         }],
+        };
+        let mut out = vec![];
+        pretty_print(&mut out, &focus_info).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("  Focal plane point 12 (FocalPlaneAFPointsUsed)\n"));
+        assert!(text.contains("positions are not recorded"));
+    }
+
+    #[test]
+    fn test_af_system_for_model() {
+        assert_eq!(AFSystem::for_model("SLT-A99V"), AFSystem::PhaseDetect);
+        assert_eq!(AFSystem::for_model("ILCE-7RM3"), AFSystem::OnSensor);
+    }
+}
@@ -1,41 +1,61 @@
-use std::fs::File;
-use std::io::Read;
-use std::mem;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
 use arw_file::byte_order;
 use arw_file::ifd;
 use arw_file::ifd::IFDTuple;
+use arw_file::limits::{Budget, Limits};
 
 const BE_MAGIC: u8 = 77;
 const LE_MAGIC: u8 = 73;
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Header {
     pub byte_order: byte_order::ByteOrder,
     pub magic_number: u16,
     pub ifd_offset: u32,
     pub ifds: Vec<ifd::IFD>,
+    // Those the file was parsed with, for reading more of it
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub limits: Limits,
+    // Offset and reason of the IFDs and values a lenient parse left out
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub skipped: Vec<(u64, String)>,
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
 }
 
 impl Header {
-    pub fn new(f: &mut File) -> Header {
-        let buf_size = mem::size_of::<Header>();
+    pub fn new<R: Read + Seek>(f: &mut R) -> io::Result<Header> {
+        Header::with_limits(f, Limits::default())
+    }
 
-        let mut buf = vec![0; buf_size];
+    // Parses the TIFF header and the IFD tree, values beyond `limits` or the
+    // end of the file being errors
+    pub fn with_limits<R: Read + Seek>(f: &mut R, limits: Limits) -> io::Result<Header> {
+        let budget = Budget::new(f, limits)?;
+        Header::parse(f, limits, budget)
+    }
 
-        match (*f).read(&mut buf) {
-            Ok(n) => {
-                if n < buf_size {
-                    panic!("Header incomplete");
-                }
-            }
-            Err(e) => panic!("Error: {}", e),
-        }
+    // As `with_limits`, but IFDs and values that cannot be read are left out
+    // and listed in `skipped`, for reporting on broken files
+    pub fn lenient<R: Read + Seek>(f: &mut R, limits: Limits) -> io::Result<Header> {
+        let budget = Budget::lenient(f, limits)?;
+        Header::parse(f, limits, budget)
+    }
+
+    fn parse<R: Read + Seek>(f: &mut R, limits: Limits, mut budget: Budget) -> io::Result<Header> {
+        let mut buf = [0; 8];
+        f.seek(SeekFrom::Start(0))?;
+        f.read_exact(&mut buf).map_err(|_| invalid_data(String::from("Header incomplete")))?;
 
         let byte_order = if buf[0] == buf[1] && buf[1] == LE_MAGIC {
             byte_order::ByteOrder::LittleEndian
         } else if buf[0] == buf[1] && buf[1] == BE_MAGIC {
             byte_order::ByteOrder::BigEndian
         } else {
-            panic!("Header byte order unknown!");
+            return Err(invalid_data(String::from("Header byte order unknown")));
         };
 
         let magic_number = byte_order.parse_u16(&buf[2..4]);
@@ -51,7 +71,18 @@ impl Header {
             let taken_offsets: Vec<IFDTuple> = offsets.drain(0..).collect();
 
             for IFDTuple { offset, tag_label } in taken_offsets {
-                let ifd = ifd::IFD::new(f, offset, &byte_order, &tag_label);
+                // A pointer back to an IFD already read would loop forever
+                if ifds.iter().any(|ifd| ifd.offset == offset) {
+                    budget.note(offset as u64, format!("IFD at {} already read", offset));
+                    continue;
+                }
+                let ifd = match ifd::IFD::new(f, offset, &byte_order, &tag_label, &mut budget) {
+                    Ok(ifd) => ifd,
+                    Err(err) => {
+                        budget.skip(offset as u64, err)?;
+                        continue;
+                    }
+                };
 
                 for sub_ifd_tuple in ifd.sub_ifd_offsets() {
                     if sub_ifd_tuple.offset != 0 {
@@ -69,11 +100,27 @@ impl Header {
             }
         }
 
-        Header {
+        Ok(Header {
             byte_order: byte_order,
             magic_number: magic_number,
             ifd_offset: ifd_offset,
             ifds: ifds,
-        }
+            limits,
+            skipped: budget.skipped,
+        })
+    }
+
+    pub fn ifds_of_type(&self, ifd_type: &str) -> Vec<&ifd::IFD> {
+        self.ifds.iter().filter(|ifd| ifd.ifd_type == ifd_type).collect()
+    }
+
+    // First entry with the given tag id among IFDs of the given type
+    pub fn entry(&self, ifd_type: &str, tag_id: u16) -> Option<&ifd::IFDEntry> {
+        self.ifds_of_type(ifd_type).into_iter().filter_map(|ifd| ifd.entry(tag_id)).next()
+    }
+
+    // The IFD parsed at the given offset, for following pointer entries
+    pub fn ifd_at(&self, offset: u32) -> Option<&ifd::IFD> {
+        self.ifds.iter().find(|ifd| ifd.offset == offset)
     }
 }
@@ -0,0 +1,193 @@
+// ICC profiles (tag 34675): the header and the description tag, enough for
+// a summary of the embedded profile
+use std::fmt;
+use std::io::{self, Write};
+
+use arw_file::byte_order::ByteOrder;
+use arw_file::header::Header;
+
+pub const ICC_PROFILE: u16 = 34675;
+
+const HEADER_LENGTH: usize = 128;
+const SIGNATURE: &[u8] = b"acsp";
+
+const DEVICE_CLASSES: [(&[u8], &str); 7] = [(b"scnr", "Input Device"),
+                                              (b"mntr", "Display Device"),
+                                              (b"prtr", "Output Device"),
+                                              (b"link", "Device Link"),
+                                              (b"spac", "Color Space Conversion"),
+                                              (b"abst", "Abstract"),
+                                              (b"nmcl", "Named Color")];
+
+const RENDERING_INTENTS: [&str; 4] = ["Perceptual",
+                                      "Media-Relative Colorimetric",
+                                      "Saturation",
+                                      "ICC-Absolute Colorimetric"];
+
+pub struct IccProfile {
+    pub size: u32,
+    pub version: (u8, u8, u8),
+    pub device_class: String,
+    pub color_space: String,
+    pub pcs: String,
+    pub rendering_intent: u32,
+    pub description: Option<String>,
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Invalid ICC profile: {}", message))
+}
+
+fn signature(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).trim_end().to_string()
+}
+
+fn u32_at(data: &[u8], pos: usize) -> u32 {
+    ByteOrder::BigEndian.parse_u32(&data[pos..pos + 4])
+}
+
+// 'desc' (version 2) or 'mluc' (version 4) text of a tag
+fn text(data: &[u8]) -> Option<String> {
+    if data.len() < 12 {
+        return None;
+    }
+    match &data[0..4] {
+        b"desc" => {
+            let length = u32_at(data, 8) as usize;
+            let ascii = data.get(12..12 + length)?;
+            Some(String::from_utf8_lossy(ascii).trim_end_matches('\0').to_string())
+        }
+        b"mluc" => {
+            if u32_at(data, 8) == 0 || data.len() < 28 {
+                return None;
+            }
+            // First record, usually en-US
+            let length = u32_at(data, 20) as usize;
+            let offset = u32_at(data, 24) as usize;
+            let utf16: Vec<u16> = data.get(offset..offset + length)?
+                .chunks(2)
+                .filter(|pair| pair.len() == 2)
+                .map(|pair| ByteOrder::BigEndian.parse_u16(pair))
+                .collect();
+            Some(String::from_utf16_lossy(&utf16).trim_end_matches('\0').to_string())
+        }
+        _ => None,
+    }
+}
+
+impl IccProfile {
+    pub fn parse(data: &[u8]) -> io::Result<IccProfile> {
+        if data.len() < HEADER_LENGTH + 4 {
+            return Err(invalid_data("shorter than its header"));
+        }
+        if &data[36..40] != SIGNATURE {
+            return Err(invalid_data("no acsp signature"));
+        }
+
+        // Tag table: signature, offset and size of each tag
+        let count = u32_at(data, HEADER_LENGTH) as usize;
+        let mut description = None;
+        for i in 0..count {
+            let pos = HEADER_LENGTH + 4 + 12 * i;
+            if pos + 12 > data.len() {
+                return Err(invalid_data("truncated tag table"));
+            }
+            if &data[pos..pos + 4] == b"desc" {
+                let offset = u32_at(data, pos + 4) as usize;
+                let size = u32_at(data, pos + 8) as usize;
+                description = data.get(offset..offset.saturating_add(size)).and_then(text);
+            }
+        }
+
+        Ok(IccProfile {
+            size: u32_at(data, 0),
+            version: (data[8], data[9] >> 4, data[9] & 0xf),
+            device_class: signature(&data[12..16]),
+            color_space: signature(&data[16..20]),
+            pcs: signature(&data[20..24]),
+            rendering_intent: u32_at(data, 64),
+            description,
+        })
+    }
+
+    pub fn device_class_name(&self) -> &str {
+        DEVICE_CLASSES.iter()
+            .find(|&&(class, _)| class == self.device_class.as_bytes())
+            .map_or(&self.device_class, |&(_, name)| name)
+    }
+
+    pub fn rendering_intent_name(&self) -> &str {
+        RENDERING_INTENTS.get(self.rendering_intent as usize).map_or("Unknown", |name| name)
+    }
+}
+
+impl fmt::Display for IccProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "  Description: {}", self.description.as_ref().map_or("none", |d| d))?;
+        writeln!(f,
+                 "  Version: {}.{}.{}",
+                 self.version.0,
+                 self.version.1,
+                 self.version.2)?;
+        writeln!(f, "  Device class: {}", self.device_class_name())?;
+        writeln!(f, "  Color space: {}", self.color_space)?;
+        writeln!(f, "  PCS: {}", self.pcs)?;
+        writeln!(f, "  Rendering intent: {}", self.rendering_intent_name())?;
+        write!(f, "  Size: {}", self.size)
+    }
+}
+
+pub fn embedded_profile(header: &Header) -> Option<&[u8]> {
+    header.ifds
+        .iter()
+        .filter(|ifd| ifd.ifd_type != "MakerNote")
+        .filter_map(|ifd| ifd.entry(ICC_PROFILE))
+        .map(|entry| entry.value_bytes.as_ref())
+        .next()
+}
+
+pub fn pretty_print<W: Write>(out: &mut W, profile: &IccProfile) -> io::Result<()> {
+    writeln!(out, "\nICC Profile\n{}", profile)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile() -> Vec<u8> {
+        let mut data = vec![0; HEADER_LENGTH];
+        data[8] = 2;
+        data[9] = 0x10;
+        data[12..16].copy_from_slice(b"mntr");
+        data[16..20].copy_from_slice(b"RGB ");
+        data[20..24].copy_from_slice(b"XYZ ");
+        data[36..40].copy_from_slice(SIGNATURE);
+        data[67] = 1;
+        // One tag, the description right after the table
+        data.extend_from_slice(&[0, 0, 0, 1]);
+        data.extend_from_slice(b"desc");
+        data.extend_from_slice(&[0, 0, 0, 144, 0, 0, 0, 21]);
+        data.extend_from_slice(b"desc\0\0\0\0\0\0\0\x09sRGB test");
+        let size = data.len() as u32;
+        data[0..4].copy_from_slice(&ByteOrder::BigEndian.u32_to_slice(size));
+        data
+    }
+
+    #[test]
+    fn test_parse() {
+        let profile = IccProfile::parse(&profile()).unwrap();
+        assert_eq!(profile.version, (2, 1, 0));
+        assert_eq!(profile.device_class_name(), "Display Device");
+        assert_eq!(profile.color_space, "RGB");
+        assert_eq!(profile.pcs, "XYZ");
+        assert_eq!(profile.rendering_intent_name(), "Media-Relative Colorimetric");
+        assert_eq!(profile.description, Some(String::from("sRGB test")));
+    }
+
+    #[test]
+    fn test_rejects_missing_signature() {
+        let mut data = profile();
+        data[36] = b'x';
+        assert!(IccProfile::parse(&data).is_err());
+    }
+}
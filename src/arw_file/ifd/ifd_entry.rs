@@ -1,4 +1,4 @@
-use std::fs::File;
+use std::io;
 use std::io::Read;
 use std::io::SeekFrom;
 use std::io::Seek;
@@ -8,15 +8,30 @@ use num::rational::Rational64;
 
 use arw_file::byte_order;
 use arw_file::ifd::tag;
+use arw_file::limits::Budget;
 
 use arw_file::format;
 
+// Serialized as the TIFF type number, which also covers unknown types
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "u16", into = "u16"))]
 pub struct FieldType {
+    id: u16,
     name: String,
     width: u8,
 }
 
+impl FieldType {
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
 impl fmt::Debug for FieldType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}({}b)", self.name, self.width)
@@ -26,84 +41,114 @@ impl fmt::Debug for FieldType {
 lazy_static! {
     pub static ref IFDFieldTypes : HashMap<u16, FieldType> = {
         let mut m = HashMap::new();
-        m.insert(1, FieldType {name: String::from("BYTE"), width: 1});
-        m.insert(2, FieldType {name: String::from("ASCII"), width: 1});
-        m.insert(3, FieldType {name: String::from("SHORT"), width: 2});
-        m.insert(4, FieldType {name: String::from("LONG"), width: 4});
-        m.insert(5, FieldType {name: String::from("RATIONAL"), width: 8});
-        m.insert(6, FieldType {name: String::from("SBYTE"), width: 4});
-        m.insert(7, FieldType {name: String::from("UNDEFINED"), width: 1});
-        m.insert(8, FieldType {name: String::from("SSHORT"), width: 2});
-        m.insert(9, FieldType {name: String::from("SLONG"), width: 4});
-        m.insert(10, FieldType {name: String::from("SRATIONAL"), width: 8});
-        m.insert(11, FieldType {name: String::from("FLOAT"), width: 4});
-        m.insert(12, FieldType {name: String::from("DOUBLE"), width: 8});
+        m.insert(1, FieldType {id: 1, name: String::from("BYTE"), width: 1});
+        m.insert(2, FieldType {id: 2, name: String::from("ASCII"), width: 1});
+        m.insert(3, FieldType {id: 3, name: String::from("SHORT"), width: 2});
+        m.insert(4, FieldType {id: 4, name: String::from("LONG"), width: 4});
+        m.insert(5, FieldType {id: 5, name: String::from("RATIONAL"), width: 8});
+        m.insert(6, FieldType {id: 6, name: String::from("SBYTE"), width: 4});
+        m.insert(7, FieldType {id: 7, name: String::from("UNDEFINED"), width: 1});
+        m.insert(8, FieldType {id: 8, name: String::from("SSHORT"), width: 2});
+        m.insert(9, FieldType {id: 9, name: String::from("SLONG"), width: 4});
+        m.insert(10, FieldType {id: 10, name: String::from("SRATIONAL"), width: 8});
+        m.insert(11, FieldType {id: 11, name: String::from("FLOAT"), width: 4});
+        m.insert(12, FieldType {id: 12, name: String::from("DOUBLE"), width: 8});
         m
     };
 }
 
+impl From<u16> for FieldType {
+    fn from(val: u16) -> FieldType {
+        u16_to_field_type(val)
+    }
+}
+
+impl From<FieldType> for u16 {
+    fn from(field_type: FieldType) -> u16 {
+        field_type.id
+    }
+}
+
 fn u16_to_field_type(val: u16) -> FieldType {
     if IFDFieldTypes.contains_key(&val) {
         IFDFieldTypes[&val].clone()
     } else {
         FieldType {
+            id: val,
             name: String::from("Unknown"),
             width: 1,
         }
     }
 }
 
+// Reduced, unless the denominator is zero: Rational64::new panics on those
+// and files do hold 0/0 for unknown values
+fn ratio(numer: i64, denom: i64) -> Rational64 {
+    if denom == 0 {
+        Rational64::new_raw(numer, denom)
+    } else {
+        Rational64::new(numer, denom)
+    }
+}
+
+// Values, rationals included, are carried as the bytes read from the file in
+// the entry's byte order, so a deserialized entry decodes as the original
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct IFDEntry {
     pub tag: tag::Tag,
     pub field_type: FieldType,
     pub count: u32, // u32 number of values, count of the indicated type
     pub value_offset: u32, // u32 the value offset OR the value, if the type fits 4bytes :)
+    #[cfg_attr(feature = "serde", serde(with = "::arw_file::serde_bytes"))]
     pub value_bytes: Vec<u8>,
     pub byte_order: byte_order::ByteOrder,
 }
 
 impl IFDEntry {
-    pub fn new(mut f: &mut File,
-               offset: u32,
-               byte_order: byte_order::ByteOrder,
-               ifd_type: &String)
-               -> IFDEntry {
-
-        match (*f).seek(SeekFrom::Start(offset as u64)) {
-            Ok(position) => {
-                if position != (offset as u64) {
-                    panic!("Error, can't seek to the ifd entry offset")
-                }
-            }
-            Err(e) => panic!("Cannot read ifd entry: {}", e),
-        }
-
-        let mut buf = vec![0; 12];
-
-        match (*f).read(&mut buf) {
-            Ok(n) => {
-                if n < 12 {
-                    panic!("IFD entry incomplete")
-                }
-            }
-            Err(e) => panic!("Error: {}", e),
-        }
+    // Reads the entry at `offset` and its value, which `budget` must allow. A
+    // value it skips is left empty.
+    pub fn new<R: Read + Seek>(f: &mut R,
+                               offset: u32,
+                               byte_order: byte_order::ByteOrder,
+                               ifd_type: &str,
+                               budget: &mut Budget)
+                               -> io::Result<IFDEntry> {
+        let mut buf = [0; 12];
+        f.seek(SeekFrom::Start(offset as u64))?;
+        f.read_exact(&mut buf)?;
         let tag_id = byte_order.parse_u16(&buf[0..2]);
 
         let field_type = u16_to_field_type(byte_order.parse_u16(&buf[2..4]));
         let count = byte_order.parse_u32(&buf[4..8]);
         let value_offset = byte_order.parse_u32(&buf[8..12]);
+        let tag = IFDEntry::tag_for_id(tag_id, ifd_type == "MakerNote");
+
+        // At most 2^32 - 1 values of 8 bytes: no overflow in u64
+        let byte_width = count as u64 * field_type.width as u64;
+        let value_bytes = if byte_width > 4 {
+                budget.claim_value(&format!("{} value", tag.label),
+                                   value_offset as u64,
+                                   byte_width)
+            } else {
+                Ok(())
+            }
+            .and_then(|_| IFDEntry::value_bytes(f, byte_width as usize, &byte_order, value_offset));
+        let value_bytes = match value_bytes {
+            Ok(value_bytes) => value_bytes,
+            Err(err) => {
+                budget.skip(offset as u64, err)?;
+                vec![]
+            }
+        };
 
-        let byte_width = (count * field_type.width as u32) as usize;
-
-        IFDEntry {
-            value_bytes: IFDEntry::value_bytes(&mut f, byte_width, &byte_order, value_offset),
-            tag: IFDEntry::tag_for_id(tag_id, *ifd_type == String::from("MakerNote")),
+        Ok(IFDEntry {
+            value_bytes,
+            tag: tag,
             field_type: field_type,
             count: count,
             value_offset: value_offset,
             byte_order: byte_order,
-        }
+        })
     }
 
     fn tag_for_id(id: u16, sony_tags: bool) -> tag::Tag {
@@ -117,7 +162,7 @@ impl IFDEntry {
             Some(tag) => (*tag).clone(),
             None => {
                 tag::Tag {
-                    id: 0,
+                    id: id,
                     ifd: false,
                     label: format!("Unknown tag {}", &id),
                     description: String::from(""),
@@ -127,39 +172,21 @@ impl IFDEntry {
 
     }
 
-    pub fn value_bytes(f: &mut File,
-                       count: usize,
-                       byte_order: &byte_order::ByteOrder,
-                       value_offset: u32)
-                       -> Vec<u8> {
-
-        let mut buf = vec![0; count];
+    // The `count` bytes of the value, from the value field when they fit
+    pub fn value_bytes<R: Read + Seek>(f: &mut R,
+                                       count: usize,
+                                       byte_order: &byte_order::ByteOrder,
+                                       value_offset: u32)
+                                       -> io::Result<Vec<u8>> {
         if count <= 4 {
-            // Fill buffer from value field
             let mut values = byte_order.u32_to_slice(value_offset).to_vec();
             values.truncate(count);
-            return values;
-        } else {
-            //
-            match (*f).seek(SeekFrom::Start(value_offset as u64)) {
-                Ok(pos) => {
-                    if pos != (value_offset as u64) {
-                        panic!("Error, can't seek to the field value offset");
-                    }
-                }
-                Err(e) => panic!("Cannot read field value: {}", e),
-            }
-
-            match (*f).read(&mut buf) {
-                Ok(n) => {
-                    if n < count {
-                        panic!("Field value incomplete");
-                    }
-                }
-                Err(e) => panic!("Error: {}", e),
-            }
-            return buf;
+            return Ok(values);
         }
+        let mut buf = vec![0; count];
+        f.seek(SeekFrom::Start(value_offset as u64))?;
+        f.read_exact(&mut buf)?;
+        Ok(buf)
     }
 
     pub fn string_value(&self) -> String {
@@ -230,7 +257,7 @@ impl IFDEntry {
         let values: Vec<Rational64> = iter.map(|bytes_arr| {
                 let num = self.byte_order.parse_u32(&bytes_arr[0..4]);
                 let denom = self.byte_order.parse_u32(&bytes_arr[4..8]);
-                Rational64::new(num as i64, denom as i64)
+                ratio(num as i64, denom as i64)
             })
             .collect();
 
@@ -246,14 +273,41 @@ impl IFDEntry {
         let values: Vec<Rational64> = iter.map(|bytes_arr| {
                 let num = self.byte_order.parse_i32(&bytes_arr[0..4]);
                 let denom = self.byte_order.parse_i32(&bytes_arr[4..8]);
-                Rational64::new(num as i64, denom as i64)
+                ratio(num as i64, denom as i64)
             })
             .collect();
 
         return Some(values);
     }
 
+    // BYTE, SHORT and LONG values widened to u32, for tags whose type varies
+    pub fn unsigned_values(&self) -> Option<Vec<u32>> {
+        match self.field_type.name.as_ref() {
+            "BYTE" | "UNDEFINED" => Some(self.value_bytes.iter().map(|b| *b as u32).collect()),
+            "SHORT" => self.short_values().map(|v| v.iter().map(|s| *s as u32).collect()),
+            "LONG" => self.long_values(),
+            _ => None,
+        }
+    }
+
     pub fn is_ifd(&self) -> bool {
         self.tag.ifd || self.tag.label == String::from("MakerNote")
     }
+
+    // Length of the header some Sony MakerNotes start with, "SONY DSC \0\0\0"
+    // or "SONY CAM \0\0\0", their IFD following it
+    pub fn maker_note_header_length(&self) -> usize {
+        let bytes = &self.value_bytes;
+        if self.tag.label == "MakerNote" && bytes.len() >= 12 && bytes.starts_with(b"SONY ") &&
+           bytes[9..12] == [0, 0, 0] {
+            12
+        } else {
+            0
+        }
+    }
+
+    // Offset of the IFD a pointer entry leads to
+    pub fn ifd_offset(&self) -> u32 {
+        self.value_offset + self.maker_note_header_length() as u32
+    }
 }
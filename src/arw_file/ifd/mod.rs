@@ -1,16 +1,18 @@
 
-use std::fs::File;
+use std::io;
 use std::io::Read;
 use std::io::SeekFrom;
 use std::io::Seek;
 
 use arw_file::byte_order;
+use arw_file::limits::Budget;
 
 mod ifd_entry;
-mod tag;
+pub mod tag;
 
 pub use self::ifd_entry::IFDEntry;
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct IFD {
     // Image File Directory
     pub entries_count: u16,
@@ -26,67 +28,43 @@ pub struct IFDTuple {
 }
 
 impl IFD {
-    pub fn new(mut f: &mut File,
-               offset: u32,
-               byte_order: &byte_order::ByteOrder,
-               ifd_type: &String)
-               -> IFD {
-        let mut buf = vec![0; 4];
-
-        match (*f).seek(SeekFrom::Start(offset as u64)) {
-            Ok(position) => {
-                if position != (offset as u64) {
-                    panic!("Error, can't seek to the offset")
-                }
-            }
-            Err(e) => panic!("Error: {}", e),
-        }
-
-        match (*f).read(&mut buf) {
-            Ok(n) => {
-                if n < 4 {
-                    panic!("IFD incomplete");
-                }
-            }
-            Err(e) => panic!("Error: {}", e),
-        }
-
+    // Reads the IFD at `offset`, its table and values counted against `budget`
+    pub fn new<R: Read + Seek>(f: &mut R,
+                               offset: u32,
+                               byte_order: &byte_order::ByteOrder,
+                               ifd_type: &str,
+                               budget: &mut Budget)
+                               -> io::Result<IFD> {
+        let mut buf = [0; 4];
+        budget.check(ifd_type, offset as u64, 2)?;
+        f.seek(SeekFrom::Start(offset as u64))?;
+        f.read_exact(&mut buf[0..2])?;
         let entries_count = byte_order.parse_u16(&buf[0..2]);
+        budget.claim(ifd_type, offset as u64, 2 + 12 * entries_count as u64 + 4)?;
 
         let mut ifd_entry_offset = offset + 2;
         let mut entries = vec![];
 
         for _ in 0..entries_count {
-            entries.push(IFDEntry::new(&mut f, ifd_entry_offset, *byte_order, &ifd_type));
+            entries.push(IFDEntry::new(f, ifd_entry_offset, *byte_order, ifd_type, budget)?);
             ifd_entry_offset += 12;
         }
 
-        match (*f).seek(SeekFrom::Start(ifd_entry_offset as u64)) {
-            Ok(position) => {
-                if position != (ifd_entry_offset as u64) {
-                    panic!("Error, can't seek to the offset")
-                }
-            }
-            Err(e) => panic!("Error: {}", e),
-        }
-
-        match (*f).read(&mut buf) {
-            Ok(n) => {
-                if n < 4 {
-                    panic!("IFD incomplete");
-                }
-            }
-            Err(e) => panic!("Error: {}", e),
-        }
+        f.seek(SeekFrom::Start(ifd_entry_offset as u64))?;
+        f.read_exact(&mut buf)?;
         let next_ifd_offset = byte_order.parse_u32(&buf[0..4]);
 
-        IFD {
+        Ok(IFD {
             entries_count: entries_count,
             entries: entries,
             next_ifd_offset: next_ifd_offset,
-            ifd_type: ifd_type.clone(),
+            ifd_type: String::from(ifd_type),
             offset: offset,
-        }
+        })
+    }
+
+    pub fn entry(&self, tag_id: u16) -> Option<&IFDEntry> {
+        self.entries.iter().find(|entry| entry.tag.id == tag_id)
     }
 
     pub fn sub_ifd_offsets(&self) -> Vec<IFDTuple> {
@@ -95,7 +73,7 @@ impl IFD {
         for entry in &self.entries {
             if entry.is_ifd() {
                 sub_ifd_offsets.push(IFDTuple {
-                    offset: entry.value_offset,
+                    offset: entry.ifd_offset(),
                     tag_label: entry.tag.label.clone(),
                 });
             }
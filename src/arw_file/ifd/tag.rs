@@ -2,6 +2,7 @@ use std::collections::HashMap;
 use std::fmt;
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Tag {
     pub id: u16,
     pub label: String,
@@ -15,6 +16,21 @@ impl fmt::Display for Tag {
     }
 }
 
+// Tag with the given label (case insensitive) in TAGS or SONY_TAGS, the
+// lowest id of those sharing it, as FocusMode does, so the choice is stable
+pub fn find_by_label(label: &str, sony_tags: bool) -> Option<&'static Tag> {
+    let tags: &HashMap<u16, Tag> = if sony_tags { &SONY_TAGS } else { &TAGS };
+    tags.values().filter(|tag| tag.label.eq_ignore_ascii_case(label)).min_by_key(|tag| tag.id)
+}
+
+// Tag id from a decimal or 0x prefixed hexadecimal number, or a label
+pub fn parse_id(spec: &str, sony_tags: bool) -> Option<u16> {
+    if spec.starts_with("0x") || spec.starts_with("0X") {
+        return u16::from_str_radix(&spec[2..], 16).ok();
+    }
+    spec.parse::<u16>().ok().or_else(|| find_by_label(spec, sony_tags).map(|tag| tag.id))
+}
+
 // Auto-generated code below
 // 2016-12-07 21:07:57 +0100
 
@@ -0,0 +1,203 @@
+// IPTC-IIM data (tag 33723): records of tagged datasets, text decoded in the
+// character set declared by the envelope's CodedCharacterSet (1:90)
+use std::io::{self, Write};
+
+use arw_file::header::Header;
+
+pub const IPTC: u16 = 33723;
+
+const TAG_MARKER: u8 = 0x1c;
+const CODED_CHARACTER_SET: (u8, u8) = (1, 90);
+// ISO 2022 escape sequence declaring UTF-8 in 1:90
+const UTF8: &[u8] = b"\x1b%G";
+
+// Record, dataset number, name
+const DATASETS: [(u8, u8, &str); 28] =
+    [(1, 0, "EnvelopeRecordVersion"),
+     (1, 90, "CodedCharacterSet"),
+     (2, 0, "ApplicationRecordVersion"),
+     (2, 5, "ObjectName"),
+     (2, 10, "Urgency"),
+     (2, 15, "Category"),
+     (2, 20, "SupplementalCategories"),
+     (2, 25, "Keywords"),
+     (2, 40, "SpecialInstructions"),
+     (2, 55, "DateCreated"),
+     (2, 60, "TimeCreated"),
+     (2, 62, "DigitalCreationDate"),
+     (2, 63, "DigitalCreationTime"),
+     (2, 80, "By-line"),
+     (2, 85, "By-lineTitle"),
+     (2, 90, "City"),
+     (2, 92, "Sub-location"),
+     (2, 95, "Province-State"),
+     (2, 100, "Country-PrimaryLocationCode"),
+     (2, 101, "Country-PrimaryLocationName"),
+     (2, 103, "OriginalTransmissionReference"),
+     (2, 105, "Headline"),
+     (2, 110, "Credit"),
+     (2, 115, "Source"),
+     (2, 116, "CopyrightNotice"),
+     (2, 118, "Contact"),
+     (2, 120, "Caption-Abstract"),
+     (2, 122, "Writer-Editor")];
+
+// Binary datasets, holding a big endian number
+const NUMERIC: [(u8, u8); 2] = [(1, 0), (2, 0)];
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Charset {
+    Utf8,
+    Latin1,
+}
+
+pub struct Dataset {
+    pub record: u8,
+    pub number: u8,
+    pub data: Vec<u8>,
+}
+
+impl Dataset {
+    pub fn name(&self) -> String {
+        DATASETS.iter()
+            .find(|&&(record, number, _)| record == self.record && number == self.number)
+            .map_or(format!("{}:{:03}", self.record, self.number),
+                    |&(_, _, name)| String::from(name))
+    }
+
+    pub fn value(&self, charset: Charset) -> String {
+        if NUMERIC.contains(&(self.record, self.number)) {
+            return self.data.iter().fold(0u64, |n, b| n << 8 | *b as u64).to_string();
+        }
+        if (self.record, self.number) == CODED_CHARACTER_SET {
+            return match self.data.as_ref() {
+                UTF8 => String::from("UTF8"),
+                _ => {
+                    let hex: Vec<String> = self.data.iter().map(|b| format!("{:02X}", b)).collect();
+                    hex.join(" ")
+                }
+            };
+        }
+        match charset {
+            Charset::Utf8 => String::from_utf8_lossy(&self.data).into_owned(),
+            Charset::Latin1 => self.data.iter().map(|b| *b as char).collect(),
+        }
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub fn parse(data: &[u8]) -> io::Result<Vec<Dataset>> {
+    let mut datasets = vec![];
+    let mut pos = 0;
+    // Padded to a multiple of 4 when stored as LONG
+    while pos < data.len() && data[pos] != 0 {
+        if data[pos] != TAG_MARKER || pos + 5 > data.len() {
+            return Err(invalid_data(format!("No IPTC dataset at {}", pos)));
+        }
+        let record = data[pos + 1];
+        let number = data[pos + 2];
+        let mut length = ((data[pos + 3] as usize) << 8) | data[pos + 4] as usize;
+        pos += 5;
+        // Extended dataset: the low bits give the size of the length field
+        if length & 0x8000 != 0 {
+            let size = length & 0x7fff;
+            if size > 4 || pos + size > data.len() {
+                return Err(invalid_data(format!("Bad extended length in {}:{}",
+                                                record,
+                                                number)));
+            }
+            length = data[pos..pos + size].iter().fold(0, |n, b| n << 8 | *b as usize);
+            pos += size;
+        }
+        if pos + length > data.len() {
+            return Err(invalid_data(format!("IPTC dataset {}:{} is truncated",
+                                            record,
+                                            number)));
+        }
+        datasets.push(Dataset {
+            record,
+            number,
+            data: data[pos..pos + length].to_vec(),
+        });
+        pos += length;
+    }
+    Ok(datasets)
+}
+
+// From CodedCharacterSet, Latin-1 when missing or not UTF-8
+pub fn charset(datasets: &[Dataset]) -> Charset {
+    let coded = datasets.iter().find(|d| (d.record, d.number) == CODED_CHARACTER_SET);
+    match coded {
+        Some(dataset) if dataset.data == UTF8 => Charset::Utf8,
+        _ => Charset::Latin1,
+    }
+}
+
+// Name and values, repeated datasets (Keywords) merged, in order of appearance
+pub fn properties(datasets: &[Dataset]) -> Vec<(String, Vec<String>)> {
+    let charset = charset(datasets);
+    let mut properties: Vec<(String, Vec<String>)> = vec![];
+    for dataset in datasets {
+        let name = dataset.name();
+        let value = dataset.value(charset);
+        match properties.iter().position(|(n, _)| *n == name) {
+            Some(i) => properties[i].1.push(value),
+            None => properties.push((name, vec![value])),
+        }
+    }
+    properties
+}
+
+pub fn embedded_data(header: &Header) -> Option<&[u8]> {
+    header.entry("Main", IPTC).map(|entry| entry.value_bytes.as_ref())
+}
+
+pub fn pretty_print<W: Write>(out: &mut W, datasets: &[Dataset]) -> io::Result<()> {
+    writeln!(out, "\nIPTC ({:?})", charset(datasets))?;
+    for (name, values) in properties(datasets) {
+        writeln!(out, "  {}: {}", name, values.join(", "))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dataset(record: u8, number: u8, data: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![TAG_MARKER, record, number, 0, data.len() as u8];
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    fn test_parse_utf8() {
+        let mut data = dataset(1, 90, UTF8);
+        data.extend(dataset(2, 25, "plaża".as_bytes()));
+        data.extend(dataset(2, 25, b"sea"));
+        data.extend(dataset(2, 0, &[0, 4]));
+        data.extend_from_slice(&[0, 0]);
+
+        let datasets = parse(&data).unwrap();
+        assert_eq!(charset(&datasets), Charset::Utf8);
+        let properties = properties(&datasets);
+        assert_eq!(properties[1],
+                   (String::from("Keywords"), vec![String::from("plaża"), String::from("sea")]));
+        assert_eq!(properties[2].1, vec!["4"]);
+    }
+
+    #[test]
+    fn test_latin1_and_extended_length() {
+        let mut data = vec![TAG_MARKER, 2, 120, 0x80, 0x02, 0, 3];
+        data.extend_from_slice(b"caf");
+        data.extend(dataset(2, 80, b"Jos\xe9"));
+        let datasets = parse(&data).unwrap();
+        assert_eq!(charset(&datasets), Charset::Latin1);
+        assert_eq!(datasets[0].value(Charset::Latin1), "caf");
+        assert_eq!(datasets[1].value(Charset::Latin1), "José");
+        assert!(parse(&[TAG_MARKER, 2, 25, 0, 9, b'a']).is_err());
+    }
+}
@@ -0,0 +1,296 @@
+// ISO base media file format (HEIF, Sony .HIF): the box walk locating the
+// Exif item through the meta box's item information (iinf) and item
+// location (iloc) boxes
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+
+use arw_file::byte_order::ByteOrder;
+
+const EXIF_ITEM: &[u8] = b"Exif";
+// Largest meta box read into memory
+const MAX_META_LENGTH: u64 = 16 << 20;
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Invalid ISOBMFF file: {}", message))
+}
+
+// An ftyp box first, as required by ISO/IEC 14496-12
+pub fn is_isobmff(data: &[u8]) -> bool {
+    data.len() >= 8 && &data[4..8] == b"ftyp"
+}
+
+// Big endian fields of a box, read in order
+struct Fields<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn new(data: &'a [u8]) -> Fields<'a> {
+        Fields { data, pos: 0 }
+    }
+
+    fn bytes(&mut self, length: usize) -> io::Result<&'a [u8]> {
+        let bytes = self.data
+            .get(self.pos..self.pos.saturating_add(length))
+            .ok_or_else(|| invalid_data("truncated box"))?;
+        self.pos += length;
+        Ok(bytes)
+    }
+
+    // 0, 2, 4 or 8 byte unsigned field
+    fn uint(&mut self, size: usize) -> io::Result<u64> {
+        if size != 0 && size != 2 && size != 4 && size != 8 {
+            return Err(invalid_data("bad field size"));
+        }
+        Ok(self.bytes(size)?.iter().fold(0, |n, b| n << 8 | *b as u64))
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(ByteOrder::BigEndian.parse_u16(self.bytes(2)?))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(ByteOrder::BigEndian.parse_u32(self.bytes(4)?))
+    }
+
+    // Version and flags of a full box
+    fn version(&mut self) -> io::Result<u8> {
+        let version = self.u8()?;
+        self.bytes(3)?;
+        Ok(version)
+    }
+}
+
+// Type, payload offset and payload length of the boxes in `data`
+fn boxes(data: &[u8]) -> io::Result<Vec<([u8; 4], usize, usize)>> {
+    let mut boxes = vec![];
+    let mut fields = Fields::new(data);
+    while fields.pos + 8 <= data.len() {
+        let start = fields.pos;
+        let size = fields.u32()? as u64;
+        let mut box_type = [0; 4];
+        box_type.copy_from_slice(fields.bytes(4)?);
+        let size = match size {
+            0 => (data.len() - start) as u64,
+            1 => fields.uint(8)?,
+            size => size,
+        };
+        let header = (fields.pos - start) as u64;
+        if size < header || start as u64 + size > data.len() as u64 {
+            return Err(invalid_data("box exceeds its parent"));
+        }
+        boxes.push((box_type, fields.pos, (size - header) as usize));
+        fields.pos = start + size as usize;
+    }
+    Ok(boxes)
+}
+
+fn child<'a>(data: &'a [u8], box_type: &[u8]) -> io::Result<Option<&'a [u8]>> {
+    Ok(boxes(data)?
+        .into_iter()
+        .find(|&(t, _, _)| t == box_type)
+        .map(|(_, offset, length)| &data[offset..offset + length]))
+}
+
+// Reads the top level meta box, skipping the others (mdat included)
+fn meta_box<R: Read + Seek>(f: &mut R) -> io::Result<Vec<u8>> {
+    let file_length = f.seek(SeekFrom::End(0))?;
+    let mut pos = 0;
+    while pos + 8 <= file_length {
+        f.seek(SeekFrom::Start(pos))?;
+        let mut header = [0; 16];
+        f.read_exact(&mut header[..8])?;
+        let mut header_length = 8;
+        let size = match ByteOrder::BigEndian.parse_u32(&header[0..4]) as u64 {
+            0 => file_length - pos,
+            1 => {
+                f.read_exact(&mut header[8..])?;
+                header_length = 16;
+                Fields::new(&header[8..]).uint(8)?
+            }
+            size => size,
+        };
+        if size < header_length || pos + size > file_length {
+            return Err(invalid_data("box exceeds the file"));
+        }
+        if &header[4..8] == b"meta" {
+            if size - header_length > MAX_META_LENGTH {
+                return Err(invalid_data("meta box too large"));
+            }
+            let mut meta = vec![0; (size - header_length) as usize];
+            f.read_exact(&mut meta)?;
+            return Ok(meta);
+        }
+        pos += size;
+    }
+    Err(io::Error::new(io::ErrorKind::NotFound, "No meta box"))
+}
+
+// Item ID of the first Exif item in iinf
+fn exif_item_id(iinf: &[u8]) -> io::Result<Option<u32>> {
+    let mut fields = Fields::new(iinf);
+    let version = fields.version()?;
+    fields.bytes(if version == 0 { 2 } else { 4 })?;
+    for (box_type, offset, length) in boxes(&iinf[fields.pos..])? {
+        if &box_type != b"infe" {
+            continue;
+        }
+        let start = fields.pos + offset;
+        let mut infe = Fields::new(&iinf[start..start + length]);
+        // Versions 0 and 1 predate item types
+        let version = infe.version()?;
+        if version < 2 {
+            continue;
+        }
+        let item_id = if version == 2 { infe.u16()? as u32 } else { infe.u32()? };
+        infe.u16()?;
+        if infe.bytes(4)? == EXIF_ITEM {
+            return Ok(Some(item_id));
+        }
+    }
+    Ok(None)
+}
+
+// File offset and length of an item stored in one extent (or contiguous
+// extents) of the file
+fn item_location(iloc: &[u8], item_id: u32) -> io::Result<Option<(u64, u64)>> {
+    let mut fields = Fields::new(iloc);
+    let version = fields.version()?;
+    let sizes = fields.u16()?;
+    let offset_size = (sizes >> 12) as usize;
+    let length_size = (sizes >> 8 & 0xf) as usize;
+    let base_offset_size = (sizes >> 4 & 0xf) as usize;
+    let index_size = if version == 1 || version == 2 { (sizes & 0xf) as usize } else { 0 };
+    let item_count = if version < 2 { fields.u16()? as u32 } else { fields.u32()? };
+
+    for _ in 0..item_count {
+        let id = if version < 2 { fields.u16()? as u32 } else { fields.u32()? };
+        let construction_method = if version == 1 || version == 2 {
+            fields.u16()? & 0xf
+        } else {
+            0
+        };
+        fields.u16()?;
+        let base_offset = fields.uint(base_offset_size)?;
+        let extent_count = fields.u16()?;
+        let mut extents = vec![];
+        for _ in 0..extent_count {
+            fields.uint(index_size)?;
+            let offset = fields.uint(offset_size)?;
+            let length = fields.uint(length_size)?;
+            let offset = base_offset.checked_add(offset)
+                .ok_or_else(|| invalid_data("extent offset overflow"))?;
+            extents.push((offset, length));
+        }
+        if id != item_id {
+            continue;
+        }
+        if construction_method != 0 {
+            return Err(invalid_data("Exif item not stored at a file offset"));
+        }
+        let (offset, mut length) = match extents.first() {
+            Some(&extent) => extent,
+            None => return Err(invalid_data("Exif item without extents")),
+        };
+        for &(extent_offset, extent_length) in &extents[1..] {
+            if Some(extent_offset) != offset.checked_add(length) {
+                return Err(invalid_data("Exif item in scattered extents"));
+            }
+            length += extent_length;
+        }
+        return Ok(Some((offset, length)));
+    }
+    Ok(None)
+}
+
+// Offset of the TIFF header of the Exif item: the item starts with the
+// 32 bit size of a prefix (usually "Exif\0\0") preceding the header
+pub fn exif_offset<R: Read + Seek>(f: &mut R) -> io::Result<u64> {
+    let not_found = || io::Error::new(io::ErrorKind::NotFound, "No Exif item");
+    let meta = meta_box(f)?;
+    // meta is a full box
+    let children = meta.get(4..).ok_or_else(|| invalid_data("truncated meta box"))?;
+    let iinf = child(children, b"iinf")?.ok_or_else(&not_found)?;
+    let item_id = exif_item_id(iinf)?.ok_or_else(&not_found)?;
+    let iloc = child(children, b"iloc")?.ok_or_else(|| invalid_data("no iloc box"))?;
+    let (offset, length) = item_location(iloc, item_id)?.ok_or_else(&not_found)?;
+
+    let mut prefix = [0; 4];
+    f.seek(SeekFrom::Start(offset))?;
+    f.read_exact(&mut prefix)?;
+    let prefix = ByteOrder::BigEndian.parse_u32(&prefix) as u64;
+    if length < 4 + prefix + 8 {
+        return Err(invalid_data("Exif item shorter than its prefix"));
+    }
+    Ok(offset + 4 + prefix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn bmff_box(box_type: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut data = ByteOrder::BigEndian.u32_to_slice(8 + payload.len() as u32).to_vec();
+        data.extend_from_slice(box_type);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    fn heif(exif: &[u8]) -> Vec<u8> {
+        let ftyp = bmff_box(b"ftyp", b"heix\0\0\0\0mif1heix");
+        let mut infe = vec![2, 0, 0, 0, 0, 1, 0, 0];
+        infe.extend_from_slice(b"hvc1");
+        let mut iinf = vec![0, 0, 0, 0, 0, 2];
+        iinf.extend(bmff_box(b"infe", &infe));
+        infe[5] = 2;
+        infe[8..12].copy_from_slice(b"Exif");
+        iinf.extend(bmff_box(b"infe", &infe));
+
+        // Version 1, 4 byte offsets and lengths, the Exif item in two extents
+        let mut meta = vec![0, 0, 0, 0];
+        meta.extend(bmff_box(b"hdlr", &[0; 24]));
+        meta.extend(bmff_box(b"iinf", &iinf));
+        let mdat = (ftyp.len() + 8 + meta.len() + 8 + 48 + 8) as u32;
+        let items: [(u8, Vec<(u32, u32)>); 2] =
+            [(1, vec![(mdat, 0)]), (2, vec![(mdat, 4), (mdat + 4, exif.len() as u32)])];
+        let mut iloc = vec![1, 0, 0, 0, 0x44, 0x00, 0, 2];
+        for &(id, ref extents) in &items {
+            iloc.extend_from_slice(&[0, id, 0, 0, 0, 0, 0, extents.len() as u8]);
+            for &(offset, length) in extents {
+                iloc.extend_from_slice(&ByteOrder::BigEndian.u32_to_slice(offset));
+                iloc.extend_from_slice(&ByteOrder::BigEndian.u32_to_slice(length));
+            }
+        }
+        meta.extend(bmff_box(b"iloc", &iloc));
+
+        let mut data = ftyp;
+        data.extend(bmff_box(b"meta", &meta));
+        let mut payload = vec![0, 0, 0, 6];
+        payload.extend_from_slice(exif);
+        data.extend(bmff_box(b"mdat", &payload));
+        data
+    }
+
+    #[test]
+    fn test_exif_offset() {
+        let data = heif(b"Exif\0\0II*\0\x08\0\0\0");
+        assert!(is_isobmff(&data));
+        let offset = exif_offset(&mut Cursor::new(&data)).unwrap() as usize;
+        assert_eq!(&data[offset..offset + 4], b"II*\0");
+    }
+
+    #[test]
+    fn test_no_exif_item() {
+        let mut data = heif(b"Exif\0\0II*\0\x08\0\0\0");
+        let position = data.windows(4).position(|w| w == b"Exif").unwrap();
+        data[position..position + 4].copy_from_slice(b"mime");
+        let err = exif_offset(&mut Cursor::new(&data)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
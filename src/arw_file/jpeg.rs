@@ -0,0 +1,144 @@
+// JPEG marker level helpers, enough to size and sanity check embedded images
+// and to find the Exif data of JPEG files
+use std::io;
+
+pub const SOI: u8 = 0xD8;
+pub const EOI: u8 = 0xD9;
+pub const SOS: u8 = 0xDA;
+pub const APP1: u8 = 0xE1;
+// Start of the APP1 payload, followed by the TIFF header
+pub const EXIF_HEADER: &[u8] = b"Exif\0\0";
+
+pub fn has_soi(data: &[u8]) -> bool {
+    data.len() >= 2 && data[0] == 0xFF && data[1] == SOI
+}
+
+// Cameras pad JPEG streams to a word or block boundary, skip trailing zeros
+pub fn has_eoi(data: &[u8]) -> bool {
+    let end = data.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+    end >= 2 && data[end - 2] == 0xFF && data[end - 1] == EOI
+}
+
+pub struct Segment {
+    pub marker: u8,
+    // Offset of the segment payload, after the length field
+    pub offset: usize,
+    pub length: usize,
+}
+
+// Marker segments preceding the entropy coded data (up to and including SOS)
+pub fn segments(data: &[u8]) -> Vec<Segment> {
+    let mut segments = vec![];
+    if !has_soi(data) {
+        return segments;
+    }
+
+    let mut pos = 2;
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xFF {
+            break;
+        }
+        let marker = data[pos + 1];
+        if marker == 0xFF {
+            // Fill byte
+            pos += 1;
+            continue;
+        }
+        if marker == EOI || (0xD0..=0xD7).contains(&marker) {
+            break;
+        }
+        let length = ((data[pos + 2] as usize) << 8) + data[pos + 3] as usize;
+        if length < 2 || pos + 2 + length > data.len() {
+            break;
+        }
+        segments.push(Segment {
+            marker,
+            offset: pos + 4,
+            length: length - 2,
+        });
+        if marker == SOS {
+            break;
+        }
+        pos += 2 + length;
+    }
+    segments
+}
+
+pub fn is_sof(marker: u8) -> bool {
+    (0xC0..=0xCF).contains(&marker) && marker != 0xC4 && marker != 0xC8 && marker != 0xCC
+}
+
+// (width, height) from the first SOFn segment
+pub fn dimensions(data: &[u8]) -> Option<(u16, u16)> {
+    segments(data)
+        .into_iter()
+        .find(|segment| is_sof(segment.marker) && segment.length >= 5)
+        .map(|sof| {
+            let height = ((data[sof.offset + 1] as u16) << 8) + data[sof.offset + 2] as u16;
+            let width = ((data[sof.offset + 3] as u16) << 8) + data[sof.offset + 4] as u16;
+            (width, height)
+        })
+}
+
+// The APP1 segment holding Exif data
+pub fn exif_segment(data: &[u8]) -> Option<Segment> {
+    segments(data).into_iter().find(|segment| {
+        segment.marker == APP1 &&
+        data[segment.offset..segment.offset + segment.length].starts_with(EXIF_HEADER)
+    })
+}
+
+// A copy of `data` with `tiff` as its Exif data, in place of the original
+// APP1 segment or right after SOI when there was none
+pub fn replace_exif(data: &[u8], tiff: &[u8]) -> io::Result<Vec<u8>> {
+    let length = 2 + EXIF_HEADER.len() + tiff.len();
+    if length > 0xFFFF {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  format!("{} bytes of Exif data do not fit an APP1 segment",
+                                          tiff.len())));
+    }
+    let (start, end) = match exif_segment(data) {
+        Some(segment) => (segment.offset - 4, segment.offset + segment.length),
+        None => (2, 2),
+    };
+    let mut jpeg = Vec::with_capacity(data.len() - (end - start) + length + 2);
+    jpeg.extend_from_slice(&data[..start]);
+    jpeg.extend_from_slice(&[0xFF, APP1, (length >> 8) as u8, length as u8]);
+    jpeg.extend_from_slice(EXIF_HEADER);
+    jpeg.extend_from_slice(tiff);
+    jpeg.extend_from_slice(&data[end..]);
+    Ok(jpeg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TINY: [u8; 21] = [0xFF, 0xD8, 0xFF, 0xC0, 0x00, 0x0B, 0x08, 0x00, 0x78, 0x00, 0xA0,
+                            0x01, 0x01, 0x11, 0x00, 0xFF, 0xD9, 0x00, 0x00, 0x00, 0x00];
+
+    #[test]
+    fn test_dimensions() {
+        assert_eq!(dimensions(&TINY), Some((160, 120)));
+    }
+
+    #[test]
+    fn test_markers() {
+        assert!(has_soi(&TINY));
+        assert!(has_eoi(&TINY));
+        assert!(!has_eoi(&TINY[..16]));
+    }
+
+    #[test]
+    fn test_replace_exif() {
+        let jpeg = replace_exif(&TINY, b"II*\0").unwrap();
+        assert_eq!(jpeg.len(), TINY.len() + 14);
+        let segment = exif_segment(&jpeg).unwrap();
+        assert_eq!((segment.offset, segment.length), (6, 10));
+        assert_eq!(dimensions(&jpeg), Some((160, 120)));
+
+        let jpeg = replace_exif(&jpeg, b"MM\0*\0\0\0\x08").unwrap();
+        assert_eq!(jpeg.len(), TINY.len() + 18);
+        assert_eq!(&jpeg[12..20], b"MM\0*\0\0\0\x08");
+    }
+}
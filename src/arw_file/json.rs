@@ -0,0 +1,161 @@
+// JSON document of a parsed file, for --json. Consumers should check
+// "version": it is bumped whenever a field is renamed, removed or changes
+// meaning, while new fields may be added within a version.
+use std::fmt::Write;
+
+use arw_file::exiftool;
+use arw_file::filter::Filter;
+use arw_file::header::Header;
+use arw_file::ifd::IFD;
+use arw_file::ifd::IFDEntry;
+
+pub const FORMAT_VERSION: u32 = 1;
+
+// A JSON string literal
+pub fn string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn array<T: ToString>(values: &[T]) -> String {
+    let items: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+    format!("[{}]", items.join(", "))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Numerator and denominator pairs, as stored (not reduced)
+fn rationals(entry: &IFDEntry, signed: bool) -> String {
+    let pairs: Vec<String> = entry.value_bytes
+        .chunks(8)
+        .filter(|pair| pair.len() == 8)
+        .map(|pair| {
+            let order = entry.byte_order;
+            if signed {
+                format!("[{}, {}]", order.parse_i32(&pair[0..4]), order.parse_i32(&pair[4..8]))
+            } else {
+                format!("[{}, {}]", order.parse_u32(&pair[0..4]), order.parse_u32(&pair[4..8]))
+            }
+        })
+        .collect();
+    format!("[{}]", pairs.join(", "))
+}
+
+// The interpreted value: a string for ASCII, an array of numbers for
+// integer types, of [numerator, denominator] pairs for rationals and null
+// for the types only given as raw bytes
+pub fn value(entry: &IFDEntry) -> String {
+    match entry.field_type.name() {
+        "ASCII" => {
+            let text = String::from_utf8_lossy(&entry.value_bytes);
+            string(text.trim_end_matches('\0'))
+        }
+        "BYTE" | "SHORT" | "LONG" => array(&entry.unsigned_values().unwrap_or_default()),
+        "SSHORT" => array(&entry.signed_short_values().unwrap_or_default()),
+        "SLONG" => array(&entry.signed_long_values().unwrap_or_default()),
+        "RATIONAL" => rationals(entry, false),
+        "SRATIONAL" => rationals(entry, true),
+        _ => String::from("null"),
+    }
+}
+
+fn entry_json(entry: &IFDEntry) -> String {
+    format!("{{\"tag\": {}, \"name\": {}, \"type\": {}, \"count\": {}, \"raw\": {}, \
+             \"value\": {}}}",
+            entry.tag.id,
+            string(&entry.tag.label),
+            string(entry.field_type.name()),
+            entry.count,
+            string(&hex(&entry.value_bytes)),
+            value(entry))
+}
+
+fn ifd_json(ifd: &IFD, entries: &[&IFDEntry]) -> String {
+    let entries: Vec<String> =
+        entries.iter().map(|entry| format!("        {}", entry_json(entry))).collect();
+    format!("    {{\n      \"type\": {},\n      \"offset\": {},\n      \"next_offset\": {},\n      \
+             \"entries\": [\n{}\n      ]\n    }}",
+            string(&ifd.ifd_type),
+            ifd.offset,
+            ifd.next_ifd_offset,
+            entries.join(",\n"))
+}
+
+// IFDs and entries left out by `filter` are omitted, as are IFDs it leaves
+// without entries
+pub fn document(filename: &str, header: &Header, filter: &Filter) -> String {
+    let mut ifds: Vec<String> = vec![];
+    for (group, ifd) in exiftool::groups(header, &[]) {
+        let entries: Vec<&IFDEntry> = ifd.entries
+            .iter()
+            .filter(|entry| filter.includes_entry(&group, entry))
+            .collect();
+        if filter.includes_ifd(&group, &ifd.ifd_type) &&
+           (!entries.is_empty() || ifd.entries.is_empty()) {
+            ifds.push(ifd_json(ifd, &entries));
+        }
+    }
+    format!("{{\n  \"version\": {},\n  \"file\": {},\n  \"byte_order\": {},\n  \
+             \"magic_number\": {},\n  \"ifd_offset\": {},\n  \"ifds\": [\n{}\n  ]\n}}",
+            FORMAT_VERSION,
+            string(filename),
+            string(&header.byte_order.to_str()),
+            header.magic_number,
+            header.ifd_offset,
+            ifds.join(",\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arw_file::byte_order::ByteOrder;
+    use arw_file::writer::{self, TiffIfd, Value};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_string() {
+        assert_eq!(string("a \"b\"\\\n\u{1}ł"), "\"a \\\"b\\\"\\\\\\n\\u0001ł\"");
+    }
+
+    #[test]
+    fn test_document() {
+        let mut ifd = TiffIfd::new();
+        ifd.set(271, Value::Ascii(String::from("SONY")));
+        ifd.set(274, Value::Short(vec![1]));
+        ifd.set(282, Value::Rational(vec![(350, 10)]));
+        let mut f = Cursor::new(writer::encode(ByteOrder::LittleEndian, &[ifd]));
+        let header = Header::new(&mut f).unwrap();
+
+        let json = document("a.arw", &header, &Filter::default());
+        assert!(json.starts_with("{\n  \"version\": 1,\n  \"file\": \"a.arw\",\n  \
+                                  \"byte_order\": \"LE\","));
+        assert!(json.contains("{\"tag\": 271, \"name\": \"Make\", \"type\": \"ASCII\", \
+                               \"count\": 5, \"raw\": \"534f4e5900\", \"value\": \"SONY\"}"));
+        assert!(json.contains("\"type\": \"SHORT\", \"count\": 1, \"raw\": \"0100\", \
+                               \"value\": [1]}"));
+        assert!(json.contains("\"value\": [[350, 10]]}"));
+
+        let filter = Filter {
+            tags: vec![String::from("Make")],
+            ..Filter::default()
+        };
+        let json = document("a.arw", &header, &filter);
+        assert!(json.contains("\"name\": \"Make\""));
+        assert!(!json.contains("\"name\": \"Orientation\""));
+    }
+}
@@ -0,0 +1,257 @@
+// Byte ranges of the structures of a file: TIFF header, IFD tables, values
+// stored out of line, strip and tile data and JPEGs, listed by offset with
+// the bytes nothing refers to and the ranges claimed twice
+use std::io::{self, Write};
+
+use arw_file::exiftool;
+use arw_file::ifd::IFD;
+use arw_file::sr2;
+
+const STRIP_OFFSETS: u16 = 273;
+const STRIP_BYTE_COUNTS: u16 = 279;
+const TILE_OFFSETS: u16 = 324;
+const TILE_BYTE_COUNTS: u16 = 325;
+const JPEG_INTERCHANGE_FORMAT: u16 = 513;
+const JPEG_INTERCHANGE_FORMAT_LENGTH: u16 = 514;
+
+pub struct Region {
+    // Relative to the TIFF header
+    pub start: u64,
+    pub length: u64,
+    pub label: String,
+    // Holds other structures, as the MakerNote block holds the Sony IFD
+    pub container: bool,
+}
+
+impl Region {
+    fn new(start: u64, length: u64, label: String) -> Region {
+        Region {
+            start,
+            length,
+            label,
+            container: false,
+        }
+    }
+
+    pub fn end(&self) -> u64 {
+        self.start + self.length
+    }
+}
+
+fn values(ifd: &IFD, tag_id: u16) -> Vec<u32> {
+    ifd.entry(tag_id).and_then(|entry| entry.unsigned_values()).unwrap_or_default()
+}
+
+// Strips or tiles, runs of adjacent ones making one region
+fn push_blocks(regions: &mut Vec<Region>, group: &str, kind: &str, ifd: &IFD, tags: (u16, u16)) {
+    let blocks: Vec<(u32, u32)> =
+        values(ifd, tags.0).into_iter().zip(values(ifd, tags.1)).collect();
+    // (start, end, first block, last block)
+    let mut runs: Vec<(u64, u64, usize, usize)> = vec![];
+    for (i, &(offset, count)) in blocks.iter().enumerate() {
+        let (start, end) = (offset as u64, offset as u64 + count as u64);
+        match runs.last_mut() {
+            Some(run) if run.1 == start => {
+                run.1 = end;
+                run.3 = i;
+            }
+            _ => runs.push((start, end, i, i)),
+        }
+    }
+    for (start, end, first, last) in runs {
+        let label = if blocks.len() == 1 {
+            format!("[{}] {}", group, kind)
+        } else if first == last {
+            format!("[{}] {} {}", group, kind, first)
+        } else {
+            format!("[{}] {}s {}-{}", group, kind, first, last)
+        };
+        regions.push(Region::new(start, end - start, label));
+    }
+}
+
+// The regions of the IFDs of `groups`, sorted by offset, longest first at
+// the same offset. Ranges claimed by several structures, as a JPEG that is
+// both strip data and JPEGInterchangeFormat, are one region.
+pub fn regions(groups: &[(String, &IFD)]) -> Vec<Region> {
+    let mut regions = vec![Region::new(0, 8, String::from("TIFF header"))];
+    for (group, ifd) in groups {
+        // Enciphered, its table and values are listed as one block below
+        if group.starts_with("SR2SubIFD") {
+            continue;
+        }
+        regions.push(Region::new(ifd.offset as u64,
+                                 2 + 12 * ifd.entries_count as u64 + 4,
+                                 format!("[{}] IFD, {} entries", group, ifd.entries_count)));
+        for entry in ifd.entries.iter().filter(|entry| entry.value_bytes.len() > 4) {
+            let label = format!("[{}] {}", group, exiftool::tag_name(group, entry));
+            let mut region =
+                Region::new(entry.value_offset as u64, entry.value_bytes.len() as u64, label);
+            region.container = entry.tag.id == exiftool::MAKER_NOTE;
+            regions.push(region);
+        }
+
+        if group == "SR2Private" {
+            let offset = sr2::first_value(ifd, sr2::SR2_SUB_IFD_OFFSET);
+            let length = sr2::first_value(ifd, sr2::SR2_SUB_IFD_LENGTH);
+            if let (Some(offset), Some(length)) = (offset, length) {
+                let label = String::from("[SR2SubIFD] IFD and values, enciphered");
+                regions.push(Region::new(offset as u64, length as u64, label));
+            }
+        }
+        if exiftool::is_maker_notes(group) {
+            continue;
+        }
+        push_blocks(&mut regions, group, "strip", ifd, (STRIP_OFFSETS, STRIP_BYTE_COUNTS));
+        push_blocks(&mut regions, group, "tile", ifd, (TILE_OFFSETS, TILE_BYTE_COUNTS));
+        let offset = sr2::first_value(ifd, JPEG_INTERCHANGE_FORMAT);
+        let length = sr2::first_value(ifd, JPEG_INTERCHANGE_FORMAT_LENGTH);
+        if let (Some(offset), Some(length)) = (offset, length) {
+            regions.push(Region::new(offset as u64, length as u64, format!("[{}] JPEG", group)));
+        }
+    }
+
+    regions.retain(|region| region.length > 0);
+    regions.sort_by_key(|region| (region.start, !region.length));
+    let mut merged: Vec<Region> = vec![];
+    for region in regions {
+        match merged.last_mut() {
+            Some(last) if last.start == region.start && last.length == region.length => {
+                last.label = format!("{} = {}", last.label, region.label);
+                last.container |= region.container;
+            }
+            _ => merged.push(region),
+        }
+    }
+    merged
+}
+
+// The number of containers holding regions[i], and the earlier regions it
+// overlaps otherwise
+fn enclosing(regions: &[Region], i: usize) -> (usize, Vec<&Region>) {
+    let region = &regions[i];
+    let mut depth = 0;
+    let mut overlapped = vec![];
+    for other in regions[..i].iter().filter(|other| other.end() > region.start) {
+        if other.container && other.end() >= region.end() {
+            depth += 1;
+        } else {
+            overlapped.push(other);
+        }
+    }
+    (depth, overlapped)
+}
+
+// Pairs of regions sharing bytes, a container and the regions inside it aside
+pub fn overlaps(regions: &[Region]) -> Vec<(&Region, &Region)> {
+    (0..regions.len())
+        .flat_map(|i| enclosing(regions, i).1.into_iter().map(move |other| (other, &regions[i])))
+        .collect()
+}
+
+fn line<W: Write>(out: &mut W, start: u64, end: u64, text: &str) -> io::Result<()> {
+    writeln!(out, "{:>10} {:>10} {:>10}  {}", start, end, end - start, text)
+}
+
+// One line per region, at absolute file offsets (the TIFF header being at
+// `base`), regions inside a container indented. Unreferenced bytes get a line
+// of their own, the padding byte keeping a structure on a word boundary
+// aside.
+pub fn pretty_print<W: Write>(out: &mut W,
+                              regions: &[Region],
+                              base: u64,
+                              file_length: u64)
+                              -> io::Result<()> {
+    writeln!(out, "{:>10} {:>10} {:>10}  Structure", "Start", "End", "Length")?;
+    if base > 0 {
+        line(out, 0, base, "Container, before the TIFF header")?;
+    }
+    let mut covered = base;
+    for (i, region) in regions.iter().enumerate() {
+        let (start, end) = (base + region.start, base + region.end());
+        if start > covered && !(covered % 2 == 1 && start == covered + 1) {
+            line(out, covered, start, "(unreferenced)")?;
+        }
+        let (depth, overlapped) = enclosing(regions, i);
+        let mut text = format!("{}{}", "  ".repeat(depth), region.label);
+        if !overlapped.is_empty() {
+            let labels: Vec<&str> = overlapped.iter().map(|other| other.label.as_ref()).collect();
+            text.push_str(&format!("  !! overlaps {}", labels.join(", ")));
+        }
+        if end > file_length {
+            text.push_str("  !! past the end of the file");
+        }
+        line(out, start, end, &text)?;
+        covered = covered.max(end);
+    }
+    if file_length > covered {
+        line(out, covered, file_length, "(unreferenced)")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arw_file::byte_order::ByteOrder;
+    use arw_file::header::Header;
+    use arw_file::writer::{self, TiffIfd, Value};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_regions() {
+        let mut sony = TiffIfd::new();
+        sony.set(0x2001, Value::Undefined(vec![0xff; 8]));
+        let mut exif = TiffIfd::new();
+        exif.set(37500, Value::MakerNote(vec![], Box::new(sony)));
+        let mut ifd0 = TiffIfd::new();
+        ifd0.set(271, Value::Ascii(String::from("SONY")));
+        ifd0.set(273, Value::Offsets(vec![vec![1; 16], vec![2; 16]]));
+        ifd0.set(279, Value::Long(vec![16, 16]));
+        ifd0.set(34665, Value::Ifds(vec![exif]));
+        let data = writer::encode(ByteOrder::LittleEndian, &[ifd0]);
+        let header = Header::new(&mut Cursor::new(&data)).unwrap();
+
+        let regions = regions(&exiftool::groups(&header, &[]));
+        let labels: Vec<&str> = regions.iter().map(|r| r.label.as_ref()).collect();
+        assert_eq!(labels,
+                   vec!["TIFF header",
+                        "[IFD0] IFD, 4 entries",
+                        "[IFD0] Make",
+                        "[IFD0] strips 0-1",
+                        "[IFD0] StripOffsets",
+                        "[IFD0] StripByteCounts",
+                        "[ExifIFD] IFD, 1 entries",
+                        "[ExifIFD] MakerNote",
+                        "[Sony] IFD, 1 entries",
+                        "[Sony] PreviewImage"]);
+        assert_eq!(regions[3].length, 32);
+        assert!(regions[7].container);
+
+        let mut out = vec![];
+        pretty_print(&mut out, &regions, 0, data.len() as u64 + 10).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("  [Sony] IFD, 1 entries\n"));
+        assert!(!text.contains("!!"));
+        assert!(text.ends_with("        10  (unreferenced)\n"));
+    }
+
+    #[test]
+    fn test_overlaps() {
+        let regions = vec![Region::new(0, 8, String::from("TIFF header")),
+                           Region::new(8, 18, String::from("[IFD0] IFD, 1 entries")),
+                           Region::new(20, 10, String::from("[IFD0] Make")),
+                           Region::new(40, 10, String::from("[IFD0] JPEG"))];
+        let mut out = vec![];
+        pretty_print(&mut out, &regions, 100, 145).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[1], "         0        100        100  Container, before the TIFF header");
+        assert_eq!(lines[4],
+                   "       120        130         10  [IFD0] Make  \
+                    !! overlaps [IFD0] IFD, 1 entries");
+        assert_eq!(lines[5], "       130        140         10  (unreferenced)");
+        assert_eq!(lines[6],
+                   "       140        150         10  [IFD0] JPEG  !! past the end of the file");
+    }
+}
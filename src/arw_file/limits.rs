@@ -0,0 +1,165 @@
+// Caps on what a file can make the parser allocate. Sizes are computed in
+// u64 from the u32 count and the field type width, so they cannot overflow,
+// and checked against the file length before anything is read.
+use std::io;
+use std::io::{Seek, SeekFrom};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limits {
+    // Bytes of a single value stored out of line
+    pub max_value_bytes: u64,
+    // Bytes of all the values and IFD tables of a file
+    pub max_total_bytes: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_value_bytes: 64 << 20,
+            max_total_bytes: 256 << 20,
+        }
+    }
+}
+
+// What is left of the limits while parsing a file
+pub struct Budget {
+    limits: Limits,
+    used: u64,
+    file_length: u64,
+    // Whether what cannot be read is skipped rather than failing the parse
+    lenient: bool,
+    // Offset of each part skipped, and why
+    pub skipped: Vec<(u64, String)>,
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl Budget {
+    pub fn new<R: Seek>(f: &mut R, limits: Limits) -> io::Result<Budget> {
+        let file_length = f.seek(SeekFrom::End(0))?;
+        Ok(Budget {
+            limits,
+            used: 0,
+            file_length,
+            lenient: false,
+            skipped: vec![],
+        })
+    }
+
+    // A budget under which `skip` records the errors instead of returning them
+    pub fn lenient<R: Seek>(f: &mut R, limits: Limits) -> io::Result<Budget> {
+        let mut budget = Budget::new(f, limits)?;
+        budget.lenient = true;
+        Ok(budget)
+    }
+
+    // Records `err` for the part at `offset` when lenient, returns it otherwise
+    pub fn skip(&mut self, offset: u64, err: io::Error) -> io::Result<()> {
+        if !self.lenient {
+            return Err(err);
+        }
+        self.note(offset, err.to_string());
+        Ok(())
+    }
+
+    // Records a part left out even by a strict parse, when lenient
+    pub fn note(&mut self, offset: u64, reason: String) {
+        if self.lenient {
+            self.skipped.push((offset, reason));
+        }
+    }
+
+    // Checks that `length` bytes at `offset` are inside the file
+    pub fn check(&self, what: &str, offset: u64, length: u64) -> io::Result<()> {
+        match offset.checked_add(length) {
+            Some(end) if end <= self.file_length => Ok(()),
+            _ => {
+                Err(invalid_data(format!("{} at {} ({} bytes) is past the end of the file ({} \
+                                          bytes)",
+                                         what,
+                                         offset,
+                                         length,
+                                         self.file_length)))
+            }
+        }
+    }
+
+    // Checks `length` bytes at `offset` as `check` does, then against the
+    // total limit, counting them as read
+    pub fn claim(&mut self, what: &str, offset: u64, length: u64) -> io::Result<()> {
+        self.check(what, offset, length)?;
+        if self.used + length > self.limits.max_total_bytes {
+            return Err(invalid_data(format!("{} brings the bytes read over the limit of {} \
+                                             bytes",
+                                            what,
+                                            self.limits.max_total_bytes)));
+        }
+        self.used += length;
+        Ok(())
+    }
+
+    // As `claim`, for a value, which the per-value limit applies to as well
+    pub fn claim_value(&mut self, what: &str, offset: u64, length: u64) -> io::Result<()> {
+        if length > self.limits.max_value_bytes {
+            return Err(invalid_data(format!("{} of {} bytes is over the limit of {} bytes",
+                                            what,
+                                            length,
+                                            self.limits.max_value_bytes)));
+        }
+        self.claim(what, offset, length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arw_file::byte_order::ByteOrder;
+    use arw_file::header::Header;
+    use arw_file::writer::{self, TiffIfd, Value};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_budget() {
+        let limits = Limits {
+            max_value_bytes: 100,
+            max_total_bytes: 150,
+        };
+        let mut budget = Budget::new(&mut Cursor::new(vec![0; 1000]), limits).unwrap();
+        assert!(budget.claim_value("Make", 0, 100).is_ok());
+        assert!(budget.claim_value("Model", 900, 101).is_err());
+        assert!(budget.claim_value("Model", 950, 60).is_err());
+        assert!(budget.claim_value("Model", 100, 60).is_err());
+        assert!(budget.claim_value("Model", 100, 50).is_ok());
+        assert!(budget.claim("IFD0", 200, 1).is_err());
+        assert!(budget.check("strip", u64::MAX, 2).is_err());
+    }
+
+    #[test]
+    fn test_header_limits() {
+        let mut ifd0 = TiffIfd::new();
+        ifd0.set(700, Value::Byte(vec![0; 100]));
+        let data = writer::encode(ByteOrder::LittleEndian, &[ifd0]);
+        let limits = Limits {
+            max_value_bytes: 50,
+            ..Limits::default()
+        };
+        assert!(Header::new(&mut Cursor::new(&data)).is_ok());
+        assert!(Header::with_limits(&mut Cursor::new(&data), limits).is_err());
+
+        let offset = ByteOrder::LittleEndian.parse_u32(&data[4..8]) as usize;
+        let mut huge = data.clone();
+        huge[offset + 6..offset + 10].copy_from_slice(&[0xff; 4]);
+        assert!(Header::new(&mut Cursor::new(&huge)).is_err());
+
+        let mut looped = data.clone();
+        looped[offset + 14..offset + 18].copy_from_slice(&data[4..8]);
+        let header = Header::new(&mut Cursor::new(&looped)).unwrap();
+        assert_eq!(header.ifds.len(), 1);
+        assert!(header.skipped.is_empty());
+        let header = Header::lenient(&mut Cursor::new(&looped), Limits::default()).unwrap();
+        let reason = format!("IFD at {} already read", offset);
+        assert_eq!(header.skipped, vec![(offset as u64, reason)]);
+    }
+}
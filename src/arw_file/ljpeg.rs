@@ -0,0 +1,346 @@
+// Lossless JPEG (ITU T.81 process 14, SOF3) decoder, as used for tiled
+// lossless compressed raw data
+use std::io;
+
+use arw_file::jpeg;
+
+const SOF3: u8 = 0xC3;
+const DHT: u8 = 0xC4;
+const DRI: u8 = 0xDD;
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Lossless JPEG: {}", message))
+}
+
+struct HuffmanTable {
+    // Smallest code, largest code (or -1) and index of the first value per code length
+    min_code: [i32; 17],
+    max_code: [i32; 17],
+    value_index: [usize; 17],
+    values: Vec<u8>,
+}
+
+impl HuffmanTable {
+    fn new(counts: &[u8], values: &[u8]) -> HuffmanTable {
+        let mut table = HuffmanTable {
+            min_code: [0; 17],
+            max_code: [-1; 17],
+            value_index: [0; 17],
+            values: values.to_vec(),
+        };
+        let mut code = 0;
+        let mut index = 0;
+        for length in 1..17 {
+            let count = counts[length - 1] as usize;
+            if count > 0 {
+                table.value_index[length] = index;
+                table.min_code[length] = code;
+                code += count as i32;
+                index += count;
+                table.max_code[length] = code - 1;
+            }
+            code <<= 1;
+        }
+        table
+    }
+
+    fn decode(&self, bits: &mut BitReader) -> io::Result<u8> {
+        let mut code = 0;
+        for length in 1..17 {
+            code = (code << 1) | bits.read(1) as i32;
+            if code <= self.max_code[length] {
+                let index = self.value_index[length] + (code - self.min_code[length]) as usize;
+                return self.values
+                    .get(index)
+                    .cloned()
+                    .ok_or_else(|| invalid_data("bad Huffman code"));
+            }
+        }
+        Err(invalid_data("bad Huffman code"))
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader {
+            data,
+            pos: 0,
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    fn fill(&mut self) {
+        while self.count <= 24 {
+            let mut byte = 0;
+            if self.pos < self.data.len() {
+                byte = self.data[self.pos];
+                if byte == 0xFF {
+                    // Stuffed zero byte, or a marker ending the entropy coded data
+                    if self.data.get(self.pos + 1) == Some(&0) {
+                        self.pos += 2;
+                    } else {
+                        byte = 0;
+                    }
+                } else {
+                    self.pos += 1;
+                }
+            }
+            self.buffer = (self.buffer << 8) | byte as u32;
+            self.count += 8;
+        }
+    }
+
+    fn read(&mut self, bits: u32) -> u32 {
+        if bits == 0 {
+            return 0;
+        }
+        self.fill();
+        let value = (self.buffer >> (self.count - bits)) & ((1 << bits) - 1);
+        self.count -= bits;
+        value
+    }
+
+    // Drops buffered bits and skips an RSTn marker
+    fn restart(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+        while self.pos + 1 < self.data.len() &&
+              !(self.data[self.pos] == 0xFF && (0xD0..=0xD7).contains(&self.data[self.pos + 1])) {
+            self.pos += 1;
+        }
+        self.pos += 2;
+    }
+}
+
+fn read_diff(table: &HuffmanTable, bits: &mut BitReader) -> io::Result<i32> {
+    let length = table.decode(bits)? as u32;
+    Ok(match length {
+        0 => 0,
+        16 => 32768,
+        _ => {
+            let value = bits.read(length) as i32;
+            if value < 1 << (length - 1) {
+                value - (1 << length) + 1
+            } else {
+                value
+            }
+        }
+    })
+}
+
+pub struct LosslessJpeg {
+    pub width: usize,
+    pub height: usize,
+    pub components: usize,
+    pub precision: u8,
+    // height rows of width * components interleaved samples
+    pub data: Vec<u16>,
+}
+
+pub fn decode(data: &[u8]) -> io::Result<LosslessJpeg> {
+    let segments = jpeg::segments(data);
+    let mut tables: Vec<Option<HuffmanTable>> = vec![None, None, None, None];
+    let mut frame: Option<(u8, usize, usize, usize)> = None;
+    let mut restart_interval = 0;
+
+    for segment in segments.iter() {
+        let payload = &data[segment.offset..segment.offset + segment.length];
+        match segment.marker {
+            DHT => {
+                let mut pos = 0;
+                while pos + 17 <= payload.len() {
+                    let id = (payload[pos] & 0x0f) as usize;
+                    let counts = &payload[pos + 1..pos + 17];
+                    let total: usize = counts.iter().map(|c| *c as usize).sum();
+                    if pos + 17 + total > payload.len() || id > 3 {
+                        return Err(invalid_data("bad DHT segment"));
+                    }
+                    let values = &payload[pos + 17..pos + 17 + total];
+                    // Differences are at most 16 bits long
+                    if values.iter().any(|&value| value > 16) {
+                        return Err(invalid_data("bad DHT value"));
+                    }
+                    tables[id] = Some(HuffmanTable::new(counts, values));
+                    pos += 17 + total;
+                }
+            }
+            SOF3 if payload.len() >= 6 => {
+                frame = Some((payload[0],
+                              ((payload[1] as usize) << 8) + payload[2] as usize,
+                              ((payload[3] as usize) << 8) + payload[4] as usize,
+                              payload[5] as usize));
+            }
+            DRI if payload.len() >= 2 => {
+                restart_interval = ((payload[0] as usize) << 8) + payload[1] as usize;
+            }
+            marker if jpeg::is_sof(marker) => {
+                return Err(invalid_data("not a lossless (SOF3) JPEG"))
+            }
+            _ => {}
+        }
+    }
+
+    let (precision, height, width, components) = match frame {
+        Some(frame) => frame,
+        None => return Err(invalid_data("missing SOF3 segment")),
+    };
+    let sos = match segments.last() {
+        Some(segment) if segment.marker == jpeg::SOS => segment,
+        _ => return Err(invalid_data("missing SOS segment")),
+    };
+    let scan = &data[sos.offset..sos.offset + sos.length];
+    if components == 0 || scan.is_empty() || scan[0] as usize != components ||
+       scan.len() < 1 + 2 * components + 3 {
+        return Err(invalid_data("unsupported scan"));
+    }
+
+    let mut component_tables: Vec<&HuffmanTable> = vec![];
+    for c in 0..components {
+        match tables[(scan[2 + 2 * c] >> 4) as usize & 3] {
+            Some(ref table) => component_tables.push(table),
+            None => return Err(invalid_data("missing Huffman table")),
+        }
+    }
+    let predictor = scan[1 + 2 * components];
+    let point_transform = (scan[3 + 2 * components] & 0x0f) as u32;
+    if !(2..=16).contains(&precision) || point_transform + 1 >= precision as u32 {
+        return Err(invalid_data("unsupported precision"));
+    }
+    let initial = 1i32 << (precision as u32 - point_transform - 1);
+
+    // Restarts are only taken at the start of a row
+    if restart_interval % width.max(1) != 0 {
+        return Err(invalid_data("restart interval not a multiple of the width"));
+    }
+    // Every sample takes at least one bit of entropy coded data, so that a
+    // frame header alone cannot make it allocate
+    let entropy_coded = &data[sos.offset + sos.length..];
+    let row_length = width * components;
+    let sample_count = match row_length.checked_mul(height) {
+        Some(count) if count as u64 <= entropy_coded.len() as u64 * 8 => count,
+        _ => return Err(invalid_data("frame larger than its data")),
+    };
+    let mut samples = vec![0u16; sample_count];
+    let mut bits = BitReader::new(entropy_coded);
+    let mut restart_row = 0;
+
+    for row in 0..height {
+        if restart_interval > 0 && row > 0 && (row * width) % restart_interval == 0 {
+            bits.restart();
+            restart_row = row;
+        }
+        for col in 0..width {
+            for (c, table) in component_tables.iter().enumerate() {
+                let i = row * row_length + col * components + c;
+                let diff = read_diff(table, &mut bits)?;
+                let left = if col > 0 { samples[i - components] as i32 } else { 0 };
+                let pred = if row == restart_row && col == 0 {
+                    initial
+                } else if row == restart_row {
+                    left
+                } else if col == 0 {
+                    samples[i - row_length] as i32
+                } else {
+                    let above = samples[i - row_length] as i32;
+                    let diagonal = samples[i - row_length - components] as i32;
+                    match predictor {
+                        1 => left,
+                        2 => above,
+                        3 => diagonal,
+                        4 => left + above - diagonal,
+                        5 => left + ((above - diagonal) >> 1),
+                        6 => above + ((left - diagonal) >> 1),
+                        7 => (left + above) >> 1,
+                        _ => 0,
+                    }
+                };
+                samples[i] = (pred + diff) as u16;
+            }
+        }
+    }
+    if point_transform > 0 {
+        for sample in samples.iter_mut() {
+            *sample <<= point_transform;
+        }
+    }
+
+    Ok(LosslessJpeg {
+        width,
+        height,
+        components,
+        precision,
+        data: samples,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2x2, one component, 8 bit, predictor 1, a single Huffman code '0' for
+    // SSSS 2 and '1' for SSSS 0
+    fn tiny_ljpeg(entropy: &[u8]) -> Vec<u8> {
+        let mut data = vec![0xFF, 0xD8];
+        data.extend_from_slice(&[0xFF, 0xC4, 0x00, 0x15, 0x00, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                                 0, 0, 0, 0, 0, 0x02, 0x00]);
+        data.extend_from_slice(&[0xFF, 0xC3, 0x00, 0x0B, 0x08, 0x00, 0x02, 0x00, 0x02, 0x01, 0x01,
+                                 0x11, 0x00]);
+        data.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x08, 0x01, 0x01, 0x00, 0x01, 0x00, 0x00]);
+        data.extend_from_slice(entropy);
+        data.extend_from_slice(&[0xFF, 0xD9]);
+        data
+    }
+
+    #[test]
+    fn test_decode() {
+        // 128 + 3, left + 0, above - 3, left + 0: bits 0 11 1 0 00 1
+        let image = decode(&tiny_ljpeg(&[0x71])).unwrap();
+        assert_eq!((image.width, image.height, image.components), (2, 2, 1));
+        assert_eq!(image.data, vec![131, 131, 128, 128]);
+    }
+
+    #[test]
+    fn test_rejects_long_differences() {
+        let mut data = tiny_ljpeg(&[0x71]);
+        // SSSS 40 for code '0'
+        data[23] = 40;
+        assert_eq!(decode(&data).err().map(|err| err.kind()), Some(io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn test_rejects_oversized_frame() {
+        // 65535x65535 from 1 byte of data
+        let mut data = tiny_ljpeg(&[0x71]);
+        data[30..34].copy_from_slice(&[0xFF; 4]);
+        assert!(decode(&data).is_err());
+    }
+
+    #[test]
+    fn test_restart_interval() {
+        // Row 0 as in test_decode, RST0, then 128 - 3 and left + 0
+        let entropy = [0x7F, 0xFF, 0xD0, 0x1F];
+        let with_dri = |interval: u8| {
+            let mut data = tiny_ljpeg(&entropy);
+            let sos = data.len() - 12 - entropy.len();
+            data.splice(sos..sos, vec![0xFF, DRI, 0x00, 0x04, 0x00, interval]);
+            data
+        };
+        assert_eq!(decode(&with_dri(2)).unwrap().data, vec![131, 131, 125, 125]);
+        assert!(decode(&with_dri(3)).is_err());
+    }
+
+    #[test]
+    fn test_rejects_baseline_jpeg() {
+        let mut data = tiny_ljpeg(&[0x71]);
+        data[26] = 0xC0;
+        assert!(decode(&data).is_err());
+    }
+}
@@ -1,43 +1,616 @@
-use std::fs::File;
-mod header;
-mod ifd;
-mod byte_order;
-mod format;
-
-pub fn pretty_print(filename: &str, header: &header::Header) {
-    println!("{} ({}), magic number: {}",
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use arw_file::limits::Limits;
+use arw_file::rebased::Rebased;
+
+pub mod header;
+pub mod icc;
+pub mod ifd;
+pub mod batch;
+pub mod byte_order;
+pub mod csv;
+pub mod diff;
+pub mod dng;
+pub mod exiftool;
+pub mod export;
+pub mod filter;
+pub mod format;
+pub mod focus;
+pub mod iptc;
+pub mod isobmff;
+pub mod jpeg;
+pub mod json;
+pub mod layout;
+pub mod limits;
+pub mod ljpeg;
+pub mod patch;
+pub mod preview;
+pub mod raw;
+pub mod rebased;
+pub mod rewrite;
+#[cfg(feature = "serde")]
+mod serde_bytes;
+pub mod sidecar;
+pub mod sr2;
+pub mod strip;
+pub mod validate;
+pub mod writer;
+pub mod xmp;
+
+// Reads `length` bytes at `offset`, checked against the file length before
+// anything is allocated
+pub fn read_at<R: Read + Seek>(f: &mut R, offset: u64, length: usize) -> io::Result<Vec<u8>> {
+    let file_length = f.seek(SeekFrom::End(0))?;
+    if offset.checked_add(length as u64).is_none_or(|end| end > file_length) {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                  format!("{} bytes at {} are past the end of the file",
+                                          length,
+                                          offset)));
+    }
+    let mut buf = vec![0; length];
+    f.seek(SeekFrom::Start(offset))?;
+    f.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+// The TIFF structure of a file: all of an ARW, DNG or TIFF file, the Exif
+// APP1 payload of a JPEG or the Exif item of a HEIF file, offsets being
+// relative to its TIFF header
+pub fn tiff_data<R: Read + Seek>(mut f: R) -> io::Result<Rebased<R>> {
+    let mut data = vec![];
+    f.seek(SeekFrom::Start(0))?;
+    f.by_ref().take(8).read_to_end(&mut data)?;
+    let base = if isobmff::is_isobmff(&data) {
+        isobmff::exif_offset(&mut f)? as usize
+    } else if jpeg::has_soi(&data) {
+        f.read_to_end(&mut data)?;
+        match jpeg::exif_segment(&data) {
+            Some(segment) => segment.offset + jpeg::EXIF_HEADER.len(),
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, "No Exif APP1 segment")),
+        }
+    } else {
+        0
+    };
+    let mut rebased = Rebased::new(f, base as i64);
+    rebased.seek(SeekFrom::Start(0))?;
+    Ok(rebased)
+}
+
+pub fn pretty_print<W: Write>(out: &mut W,
+                              filename: &str,
+                              header: &header::Header,
+                              filter: &filter::Filter)
+                              -> io::Result<()> {
+    writeln!(out,
+             "{} ({}), magic number: {}",
              filename,
              header.byte_order.to_str(),
-             header.magic_number);
-    println!("IFDs count: {}, first IFD offset: {}",
+             header.magic_number)?;
+    writeln!(out,
+             "IFDs count: {}, first IFD offset: {}",
              header.ifds.len(),
-             header.ifd_offset);
+             header.ifd_offset)?;
 
-    for i in 0..header.ifds.len() {
-        let ref ifd = header.ifds[i];
-        println!("\nIFD {} ({}), entries: {}, offset: {}, next_offset: {} ",
+    for (i, (group, ifd)) in exiftool::groups(header, &[]).into_iter().enumerate() {
+        let entries: Vec<&ifd::IFDEntry> = ifd.entries
+            .iter()
+            .filter(|entry| filter.includes_entry(&group, entry))
+            .collect();
+        // IFDs left without entries by the filter are not worth a heading
+        if !filter.includes_ifd(&group, &ifd.ifd_type) ||
+           (entries.is_empty() && !ifd.entries.is_empty()) {
+            continue;
+        }
+        writeln!(out,
+                 "\nIFD {} ({}), entries: {}, offset: {}, next_offset: {} ",
                  i + 1,
                  ifd.ifd_type,
                  ifd.entries_count,
                  ifd.offset,
-                 ifd.next_ifd_offset);
-        for entry in &ifd.entries {
-            println!("  {} ({:?}, {}): {}",
+                 ifd.next_ifd_offset)?;
+        for entry in entries {
+            writeln!(out,
+                     "  {} ({:?}, {}): {}",
                      entry.tag,
                      entry.field_type,
                      entry.count,
-                     entry.string_value());
+                     entry.string_value())?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy)]
+pub enum InfoFormat {
+    Text,
+    Json,
+    // exiftool -s lines, with -G1 group names when `groups` is set
+    Exiftool { groups: bool },
+    // exiftool -X
+    Rdf,
+    // Byte ranges of the structures
+    Layout,
+}
+
+// Writes the info of one file to `out`. Rdf only writes its rdf:Description,
+// info_files adding the document around them.
+pub fn info<W: Write>(out: &mut W,
+                      filename: &str,
+                      show_focus: bool,
+                      format: InfoFormat,
+                      filter: &filter::Filter,
+                      limits: Limits)
+                      -> io::Result<()> {
+    let mut file_handle = tiff_data(File::open(filename)?)?;
+    let header = header::Header::with_limits(&mut file_handle, limits)?;
+    match format {
+        InfoFormat::Text => {}
+        InfoFormat::Json => return writeln!(out, "{}", json::document(filename, &header, filter)),
+        InfoFormat::Exiftool { groups } => {
+            let sr2_ifds = sr2::sr2_ifds(&mut file_handle, &header);
+            let groups_ifds = exiftool::groups(&header, &sr2_ifds);
+            for (group, name, value) in exiftool::tags(&groups_ifds, filter) {
+                let group = Some(group.as_ref()).filter(|_| groups);
+                writeln!(out, "{}", exiftool::line(group, &name, &value))?;
+            }
+            return Ok(());
+        }
+        InfoFormat::Rdf => {
+            let sr2_ifds = sr2::sr2_ifds(&mut file_handle, &header);
+            let tags = exiftool::tags(&exiftool::groups(&header, &sr2_ifds), filter);
+            return write!(out, "{}", exiftool::rdf_description(filename, &tags));
+        }
+        InfoFormat::Layout => {
+            let sr2_ifds = sr2::sr2_ifds(&mut file_handle, &header);
+            let regions = layout::regions(&exiftool::groups(&header, &sr2_ifds));
+            let file_length = file_handle.get_mut().seek(SeekFrom::End(0))?;
+            writeln!(out, "{}, {} bytes", filename, file_length)?;
+            return layout::pretty_print(out, &regions, file_handle.base() as u64, file_length);
+        }
+    }
+    pretty_print(out, filename, &header, filter)?;
+    if !filter.is_empty() {
+        if show_focus {
+            focus::pretty_print(out, &focus::FocusInfo::new(&header))?;
+        }
+        return Ok(());
+    }
+
+    if let Some(data) = iptc::embedded_data(&header) {
+        match iptc::parse(data) {
+            Ok(datasets) => iptc::pretty_print(out, &datasets)?,
+            Err(err) => eprintln!("Warning: {}: {}", filename, err),
+        }
+    }
+    if let Some(data) = icc::embedded_profile(&header) {
+        match icc::IccProfile::parse(data) {
+            Ok(profile) => icc::pretty_print(out, &profile)?,
+            Err(err) => eprintln!("Warning: {}: {}", filename, err),
+        }
+    }
+
+    if show_focus {
+        focus::pretty_print(out, &focus::FocusInfo::new(&header))?;
+    }
+    Ok(())
+}
+
+// Prints the info of the files `paths` name, directories expanded per
+// `options`. Several files make a JSON array, and -s output has a
+// "======== FILE" line before each file as exiftool prints. Returns the
+// number of paths that failed, each reported on stderr.
+pub fn info_files(paths: &[String],
+                  options: &batch::Options,
+                  show_focus: bool,
+                  format: InfoFormat,
+                  filter: &filter::Filter)
+                  -> io::Result<usize> {
+    let (files, errors) = options.files(paths);
+    for (path, err) in &errors {
+        eprintln!("{}: {}", path, err);
+    }
+    let single = paths.len() == 1 && files.len() == 1 && files[0] == paths[0];
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let separator = match format {
+        InfoFormat::Text => "\n",
+        InfoFormat::Json if !single => ",\n",
+        _ => "",
+    };
+    match format {
+        InfoFormat::Json if !single => writeln!(out, "[")?,
+        InfoFormat::Rdf => write!(out, "{}", exiftool::RDF_HEADER)?,
+        _ => {}
+    }
+    let failures = batch::run(&files, options.jobs, separator, &mut out, |filename, buffer| {
+        if let InfoFormat::Exiftool { .. } = format {
+            if !single {
+                writeln!(buffer, "======== {}", filename)?;
+            }
+        }
+        info(buffer, filename, show_focus, format, filter, options.limits)
+    })?;
+    match format {
+        InfoFormat::Json if !single => writeln!(out, "]")?,
+        InfoFormat::Rdf => write!(out, "{}", exiftool::RDF_FOOTER)?,
+        _ => {}
+    }
+    Ok(errors.len() + failures)
+}
+
+// Prints a CSV (or TSV, per `separator`) table of the `tags` of each file,
+// directories expanded per `options`. Files that cannot be read are left out
+// and reported on stderr; the number of them is returned.
+pub fn table(paths: &[String],
+             options: &batch::Options,
+             tags: &[String],
+             separator: char)
+             -> io::Result<usize> {
+    let columns: Vec<csv::Column> =
+        tags.iter().map(|spec| csv::Column::parse(spec)).collect::<io::Result<_>>()?;
+    let (files, errors) = options.files(paths);
+    for (path, err) in &errors {
+        eprintln!("{}: {}", path, err);
+    }
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    writeln!(out, "{}", csv::title_row(&columns, separator))?;
+    let limits = options.limits;
+    let failures = batch::run(&files, options.jobs, "", &mut out, |filename, buffer| {
+        let mut file_handle = tiff_data(File::open(filename)?)?;
+        let header = header::Header::with_limits(&mut file_handle, limits)?;
+        writeln!(buffer, "{}", csv::file_row(filename, &header, &columns, separator))
+    })?;
+    Ok(errors.len() + failures)
+}
+
+// Prints the tags that differ between files `a` and `b`
+pub fn diff(a: &str,
+            b: &str,
+            ignore_volatile: bool,
+            filter: &filter::Filter,
+            limits: Limits)
+            -> io::Result<()> {
+    let mut a_handle = tiff_data(File::open(a)?)?;
+    let a_header = header::Header::with_limits(&mut a_handle, limits)?;
+    let a_sr2_ifds = sr2::sr2_ifds(&mut a_handle, &a_header);
+    let mut b_handle = tiff_data(File::open(b)?)?;
+    let b_header = header::Header::with_limits(&mut b_handle, limits)?;
+    let b_sr2_ifds = sr2::sr2_ifds(&mut b_handle, &b_header);
+    let diffs = diff::diff(&exiftool::groups(&a_header, &a_sr2_ifds),
+                           &exiftool::groups(&b_header, &b_sr2_ifds),
+                           filter,
+                           ignore_volatile);
+    let stdout = io::stdout();
+    diff::pretty_print(&mut stdout.lock(), a, b, &diffs)
+}
+
+// Prints the findings of validate::validate for the files `paths` name,
+// directories expanded per `options`. Returns the number of files with
+// errors or that could not be read.
+pub fn validate_files(paths: &[String], options: &batch::Options) -> io::Result<usize> {
+    let (files, errors) = options.files(paths);
+    for (path, err) in &errors {
+        eprintln!("{}: {}", path, err);
+    }
+    let invalid = AtomicUsize::new(0);
+    let limits = options.limits;
+    let stdout = io::stdout();
+    let failures = batch::run(&files, options.jobs, "", &mut stdout.lock(), |filename, buffer| {
+        let mut file_handle = tiff_data(File::open(filename)?)?;
+        let header = header::Header::lenient(&mut file_handle, limits)?;
+        let sr2_ifds = sr2::sr2_ifds(&mut file_handle, &header);
+        let base = file_handle.base() as u64;
+        let length = file_handle.get_mut().seek(SeekFrom::End(0))? - base;
+        let findings =
+            validate::validate(&header, &exiftool::groups(&header, &sr2_ifds), length);
+        if findings.iter().any(|finding| finding.severity == validate::Severity::Error) {
+            invalid.fetch_add(1, Ordering::SeqCst);
+        }
+        validate::pretty_print(buffer, filename, &findings, base)
+    })?;
+    Ok(errors.len() + failures + invalid.load(Ordering::SeqCst))
+}
+
+// Lists embedded JPEGs, or writes the one at `index` to `output` (stdout if None)
+pub fn preview(filename: &str,
+               index: Option<usize>,
+               output: Option<&str>,
+               limits: Limits)
+               -> io::Result<()> {
+    let mut file_handle = tiff_data(File::open(filename)?)?;
+    let header = header::Header::with_limits(&mut file_handle, limits)?;
+    let jpegs = preview::embedded_jpegs(&mut file_handle, &header)?;
+
+    let index = match index {
+        Some(index) => index,
+        None => {
+            for (i, jpeg) in jpegs.iter().enumerate() {
+                println!("{}: {}", i, jpeg);
+            }
+            return Ok(());
+        }
+    };
+
+    let jpeg = match jpegs.get(index) {
+        Some(jpeg) => jpeg,
+        None => {
+            return Err(io::Error::new(io::ErrorKind::NotFound,
+                                      format!("No embedded JPEG with index {}", index)))
+        }
+    };
+    match jpeg.status {
+        preview::JpegStatus::Valid => {}
+        preview::JpegStatus::Truncated => eprintln!("Warning: {} is truncated", jpeg.label),
+        preview::JpegStatus::Invalid => {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("{} is not a valid JPEG", jpeg.label)))
         }
     }
+
+    let data = preview::read_jpeg(&mut file_handle, jpeg)?;
+    match output {
+        Some(path) => File::create(path)?.write_all(&data),
+        None => io::stdout().write_all(&data),
+    }
 }
 
-pub fn info(filename: &str) {
-    let mut file_handle;
-    match File::open(filename) {
-        Ok(handle) => file_handle = handle,
-        Err(_e) => panic!("Handle error!"),
+// Writes the undemosaiced CFA data as PGM or TIFF to `output` (stdout if None)
+pub fn extract_raw(filename: &str,
+                   format: export::RawFormat,
+                   crop: bool,
+                   output: Option<&str>,
+                   limits: Limits)
+                   -> io::Result<()> {
+    let mut file_handle = tiff_data(File::open(filename)?)?;
+    let header = header::Header::with_limits(&mut file_handle, limits)?;
+    let mut image = raw::decode(&mut file_handle, &header)?;
+
+    let raw_ifd = raw::RawIfd::locate(&header).expect("raw IFD located by decode");
+    let sr2_sub_ifd = sr2::sr2_sub_ifd(&mut file_handle, &header);
+    let levels = export::Levels::new(&header, &raw_ifd, sr2_sub_ifd.as_ref());
+    if crop {
+        match export::crop_area(&raw_ifd) {
+            Some(area) => image = export::crop(&image, area),
+            None => eprintln!("Warning: no DefaultCropOrigin/DefaultCropSize, not cropping"),
+        }
+    }
+
+    let data = match format {
+        export::RawFormat::Pgm => export::pgm_bytes(&image, &levels),
+        export::RawFormat::Tiff => export::tiff_bytes(&image, &levels),
+    };
+    match output {
+        Some(path) => File::create(path)?.write_all(&data),
+        None => io::stdout().write_all(&data),
     }
+}
 
-    let header = header::Header::new(&mut file_handle);
-    pretty_print(&filename, &header);
+// Converts to DNG, by default next to the input with a .dng extension
+pub fn to_dng(filename: &str, output: Option<&str>, limits: Limits) -> io::Result<()> {
+    let mut file_handle = tiff_data(File::open(filename)?)?;
+    let header = header::Header::with_limits(&mut file_handle, limits)?;
+    let data = dng::dng_bytes(&mut file_handle, &header)?;
+
+    let path = match output {
+        Some(path) => String::from(path),
+        None => {
+            let stem = match filename.rfind('.') {
+                Some(dot) if !filename[dot..].contains('/') => &filename[..dot],
+                _ => filename,
+            };
+            format!("{}.dng", stem)
+        }
+    };
+    File::create(&path)?.write_all(&data)?;
+    println!("Wrote {}", path);
+    Ok(())
+}
+
+// Applies TAG=VALUE assignments in place, in the first IFD holding the tag
+pub fn set_tags(filename: &str,
+                assignments: &[String],
+                ifd_type: Option<&str>,
+                limits: Limits)
+                -> io::Result<()> {
+    let file = OpenOptions::new().read(true).write(true).open(filename)?;
+    let mut file_handle = tiff_data(file)?;
+    let header = header::Header::with_limits(&mut file_handle, limits)?;
+
+    for assignment in assignments {
+        let (spec, text) = match assignment.find('=') {
+            Some(i) => (&assignment[..i], &assignment[i + 1..]),
+            None => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                          format!("Expected TAG=VALUE, got {}", assignment)))
+            }
+        };
+        let target = exiftool::groups(&header, &[])
+            .into_iter()
+            .filter(|&(ref group, ifd)| match ifd_type {
+                Some(ifd_type) => filter::ifd_matches(ifd_type, group, &ifd.ifd_type),
+                None => ifd.ifd_type != "MakerNote",
+            })
+            .filter_map(|(_, ifd)| {
+                ifd::tag::parse_id(spec, ifd.ifd_type == "MakerNote")
+                    .and_then(|tag_id| ifd.entry(tag_id))
+                    .map(|entry| (ifd, entry))
+            })
+            .next();
+        let (ifd, entry) = match target {
+            Some(target) => target,
+            None => {
+                return Err(io::Error::new(io::ErrorKind::NotFound,
+                                          format!("No {} entry to edit in place", spec)))
+            }
+        };
+        let value = patch::parse_value(entry, text)?;
+        patch::patch_entry(&mut file_handle, ifd, entry.tag.id, &value)?;
+        println!("{} ({}): {}", entry.tag.label, ifd.ifd_type, text);
+    }
+    Ok(())
+}
+
+// Writes a copy without location, serial numbers and owner data, by default
+// next to the input with a _stripped suffix
+pub fn strip(filename: &str,
+             output: Option<&str>,
+             maker_note: bool,
+             limits: Limits)
+             -> io::Result<()> {
+    let mut file_handle = tiff_data(File::open(filename)?)?;
+    let header = header::Header::with_limits(&mut file_handle, limits)?;
+    // Embedded TIFF data is written back into a copy of the container
+    let original = if file_handle.base() > 0 {
+        let mut data = vec![];
+        let file = file_handle.get_mut();
+        file.seek(SeekFrom::Start(0))?;
+        file.read_to_end(&mut data)?;
+        if !jpeg::has_soi(&data) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "Only TIFF based and JPEG files can be stripped"));
+        }
+        Some(data)
+    } else {
+        None
+    };
+    let mut tiff = if maker_note {
+        rewrite::TiffFile::read_opaque_maker_note(&mut file_handle, &header)?
+    } else {
+        rewrite::TiffFile::read(&mut file_handle, &header)?
+    };
+    let removed = strip::strip(&mut tiff, maker_note);
+
+    let path = match output {
+        Some(path) => String::from(path),
+        None => {
+            match filename.rfind('.') {
+                Some(dot) if !filename[dot..].contains('/') => {
+                    format!("{}_stripped{}", &filename[..dot], &filename[dot..])
+                }
+                _ => format!("{}_stripped", filename),
+            }
+        }
+    };
+    let data = match original {
+        Some(jpeg) => jpeg::replace_exif(&jpeg, &tiff.to_bytes())?,
+        None => tiff.to_bytes(),
+    };
+    File::create(&path)?.write_all(&data)?;
+
+    if removed.is_empty() {
+        println!("Nothing to remove");
+    }
+    for entry in &removed {
+        println!("Removed {} (tag {:#06x}) from {}", entry.name, entry.tag_id, entry.ifd_type);
+    }
+    println!("Wrote {}", path);
+    Ok(())
+}
+
+// Prints the properties of the embedded XMP packet, merged with those of the
+// sidecar, `sidecar` or the .xmp file next to the input, when there is one
+pub fn xmp(filename: &str,
+           sidecar: Option<&str>,
+           prefer_sidecar: bool,
+           limits: Limits)
+           -> io::Result<()> {
+    let mut file_handle = tiff_data(File::open(filename)?)?;
+    let header = header::Header::with_limits(&mut file_handle, limits)?;
+    let embedded = match xmp::embedded_packet(&header) {
+        Some(packet) => xmp::parse(packet)?,
+        None => vec![],
+    };
+
+    let sidecar_path = sidecar.map_or_else(|| sidecar::sidecar_path(filename), String::from);
+    let properties = match File::open(&sidecar_path) {
+        Ok(mut sidecar_file) => {
+            let mut packet = vec![];
+            sidecar_file.read_to_end(&mut packet)?;
+            sidecar::merge(embedded, xmp::parse(&packet)?, prefer_sidecar)
+        }
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound && sidecar.is_none() => embedded,
+        Err(err) => return Err(err),
+    };
+
+    if properties.is_empty() {
+        println!("No XMP properties");
+    }
+    for property in properties {
+        println!("{}", property);
+    }
+    Ok(())
+}
+
+// Writes an XMP sidecar from the file's tags and embedded XMP packet, next to
+// the input unless `output` is given. An existing sidecar is left alone.
+pub fn write_sidecar(filename: &str, output: Option<&str>, limits: Limits) -> io::Result<()> {
+    let mut file_handle = tiff_data(File::open(filename)?)?;
+    let header = header::Header::with_limits(&mut file_handle, limits)?;
+    let embedded = match xmp::embedded_packet(&header) {
+        Some(packet) => xmp::parse(packet)?,
+        None => vec![],
+    };
+    let properties = sidecar::merge(sidecar::properties(&header), embedded, false);
+
+    let path = match output {
+        Some(path) => String::from(path),
+        None => {
+            let path = sidecar::sidecar_path(filename);
+            if Path::new(&path).exists() {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                                          format!("{} already exists, use -o to write \
+                                                   elsewhere",
+                                                  path)));
+            }
+            path
+        }
+    };
+    File::create(&path)?.write_all(sidecar::packet(&properties).as_bytes())?;
+    println!("Wrote {}", path);
+    Ok(())
+}
+
+// Prints a summary of the embedded ICC profile, writing it to `output` if given
+pub fn icc(filename: &str, output: Option<&str>, limits: Limits) -> io::Result<()> {
+    let mut file_handle = tiff_data(File::open(filename)?)?;
+    let header = header::Header::with_limits(&mut file_handle, limits)?;
+    let data = match icc::embedded_profile(&header) {
+        Some(data) => data,
+        None => return Err(io::Error::new(io::ErrorKind::NotFound, "No ICC profile")),
+    };
+    let profile = icc::IccProfile::parse(data)?;
+    println!("{}", profile);
+    if let Some(path) = output {
+        File::create(path)?.write_all(data)?;
+        println!("Wrote {}", path);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arw_file::byte_order::ByteOrder;
+    use arw_file::writer::{self, TiffIfd, Value};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_tiff_data_of_jpeg() {
+        let mut ifd = TiffIfd::new();
+        ifd.set(271, Value::Ascii(String::from("SONY")));
+        let tiff = writer::encode(ByteOrder::LittleEndian, &[ifd]);
+        let jpeg = jpeg::replace_exif(&[0xFF, jpeg::SOI, 0xFF, jpeg::EOI], &tiff).unwrap();
+
+        let mut f = tiff_data(Cursor::new(jpeg)).unwrap();
+        assert_eq!(f.base(), 12);
+        let header = header::Header::new(&mut f).unwrap();
+        assert_eq!(header.entry("Main", 271).and_then(|e| e.ascii_value()),
+                   Some(String::from("SONY\0")));
+        assert!(tiff_data(Cursor::new(vec![0xFF, jpeg::SOI, 0xFF, jpeg::EOI])).is_err());
+    }
 }
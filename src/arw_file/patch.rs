@@ -0,0 +1,186 @@
+// In-place editing: overwrites an entry's value in its existing slot, the
+// 4 inline bytes or the out-of-line area it already occupies, leaving every
+// other byte of the file where it is.
+use std::io;
+use std::io::{Seek, SeekFrom, Write};
+
+use arw_file::ifd::{IFD, IFDEntry};
+use arw_file::writer::Value;
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn parse_list<T: ::std::str::FromStr>(text: &str) -> Option<Vec<T>> {
+    text.split(&[',', ' '][..])
+        .filter(|item| !item.is_empty())
+        .map(|item| item.parse::<T>().ok())
+        .collect()
+}
+
+fn parse_fractions<T: ::std::str::FromStr + From<u8>>(text: &str) -> Option<Vec<(T, T)>> {
+    text.split(&[',', ' '][..])
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            let mut parts = item.splitn(2, '/');
+            let numerator = parts.next().and_then(|n| n.parse::<T>().ok());
+            let denominator = match parts.next() {
+                Some(d) => d.parse::<T>().ok(),
+                None => Some(T::from(1)),
+            };
+            match (numerator, denominator) {
+                (Some(n), Some(d)) => Some((n, d)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+// Parses text as a value of the entry's own field type: a string for ASCII,
+// comma or space separated numbers, n/d fractions for rationals
+pub fn parse_value(entry: &IFDEntry, text: &str) -> io::Result<Value> {
+    let value = match entry.field_type.name() {
+        "ASCII" => Some(Value::Ascii(String::from(text))),
+        "BYTE" => parse_list(text).map(Value::Byte),
+        "SHORT" => parse_list(text).map(Value::Short),
+        "LONG" => parse_list(text).map(Value::Long),
+        "SLONG" => parse_list(text).map(Value::SLong),
+        "SSHORT" => {
+            parse_list::<i16>(text).map(|values| {
+                let mut bytes = vec![];
+                for v in &values {
+                    bytes.extend_from_slice(&entry.byte_order.u16_to_slice(*v as u16));
+                }
+                Value::Raw(8, values.len() as u32, bytes)
+            })
+        }
+        "RATIONAL" => parse_fractions(text).map(Value::Rational),
+        "SRATIONAL" => parse_fractions(text).map(Value::SRational),
+        name => {
+            return Err(invalid_input(format!("Editing {} values is not supported", name)));
+        }
+    };
+    value.ok_or_else(|| {
+        invalid_input(format!("Invalid {} value for {}: {}",
+                              entry.field_type.name(),
+                              entry.tag.label,
+                              text))
+    })
+}
+
+// Overwrites the value of an entry of `ifd`, refusing when the new encoding
+// does not fit in 4 bytes and is larger than the out-of-line area the
+// current value occupies
+pub fn patch_entry<F: Write + Seek>(f: &mut F,
+                                    ifd: &IFD,
+                                    tag_id: u16,
+                                    value: &Value)
+                                    -> io::Result<()> {
+    let index = match ifd.entries.iter().position(|entry| entry.tag.id == tag_id) {
+        Some(index) => index,
+        None => {
+            return Err(io::Error::new(io::ErrorKind::NotFound,
+                                      format!("No tag {} in {}", tag_id, ifd.ifd_type)))
+        }
+    };
+    let entry = &ifd.entries[index];
+    let byte_order = entry.byte_order;
+    let entry_offset = ifd.offset as u64 + 2 + 12 * index as u64;
+
+    // Where the value goes follows from its new encoding: the value field
+    // when it fits in 4 bytes, otherwise the out-of-line area of the old one
+    let mut bytes = value.bytes(byte_order);
+    let old_inline = entry.value_bytes.len() <= 4;
+    let inline = bytes.len() <= 4;
+    let slot = if inline {
+        4
+    } else if old_inline {
+        0
+    } else {
+        entry.value_bytes.len()
+    };
+    if bytes.len() > slot {
+        return Err(invalid_input(format!("{} needs {} bytes, its slot holds {}",
+                                         entry.tag.label,
+                                         bytes.len(),
+                                         if old_inline { 4 } else { slot })));
+    }
+    bytes.resize(slot, 0);
+
+    let mut header = vec![];
+    header.extend_from_slice(&byte_order.u16_to_slice(value.field_type()));
+    header.extend_from_slice(&byte_order.u32_to_slice(value.count()));
+    f.seek(SeekFrom::Start(entry_offset + 2))?;
+    f.write_all(&header)?;
+
+    let value_offset = if inline { entry_offset + 8 } else { entry.value_offset as u64 };
+    f.seek(SeekFrom::Start(value_offset))?;
+    f.write_all(&bytes)?;
+    if inline && !old_inline {
+        // The area of the old value, no longer referenced
+        f.seek(SeekFrom::Start(entry.value_offset as u64))?;
+        f.write_all(&vec![0; entry.value_bytes.len()])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arw_file::byte_order::ByteOrder;
+    use arw_file::header::Header;
+    use arw_file::writer::{self, TiffIfd};
+    use std::io::Cursor;
+
+    fn sample() -> Cursor<Vec<u8>> {
+        let mut ifd0 = TiffIfd::new();
+        ifd0.set(274, Value::Short(vec![1]));
+        ifd0.set(315, Value::Ascii(String::from("Jonathan Doe")));
+        Cursor::new(writer::encode(ByteOrder::BigEndian, &[ifd0]))
+    }
+
+    #[test]
+    fn test_patch_in_place() {
+        let mut f = sample();
+        let length = f.get_ref().len();
+        let header = Header::new(&mut f).unwrap();
+        let ifd0 = &header.ifds[0];
+        let artist = parse_value(ifd0.entry(315).unwrap(), "John Doe").unwrap();
+        let orientation = parse_value(ifd0.entry(274).unwrap(), "6").unwrap();
+        patch_entry(&mut f, ifd0, 315, &artist).unwrap();
+        patch_entry(&mut f, ifd0, 274, &orientation).unwrap();
+        assert_eq!(f.get_ref().len(), length);
+
+        f.set_position(0);
+        let header = Header::new(&mut f).unwrap();
+        assert_eq!(header.entry("Main", 315).unwrap().ascii_value().unwrap(), "John Doe\0");
+        assert_eq!(header.entry("Main", 274).unwrap().short_values(), Some(vec![6]));
+    }
+
+    #[test]
+    fn test_patch_moves_value_inline() {
+        let mut f = sample();
+        let header = Header::new(&mut f).unwrap();
+        let ifd0 = &header.ifds[0];
+        let artist_offset = ifd0.entry(315).unwrap().value_offset as usize;
+        let artist = parse_value(ifd0.entry(315).unwrap(), "Bob").unwrap();
+        patch_entry(&mut f, ifd0, 315, &artist).unwrap();
+        assert_eq!(&f.get_ref()[artist_offset..artist_offset + 13], &[0; 13]);
+
+        f.set_position(0);
+        let header = Header::new(&mut f).unwrap();
+        let artist = header.entry("Main", 315).unwrap();
+        assert_eq!(artist.count, 4);
+        assert_eq!(artist.ascii_value().unwrap(), "Bob\0");
+    }
+
+    #[test]
+    fn test_patch_refuses_larger_value() {
+        let mut f = sample();
+        let header = Header::new(&mut f).unwrap();
+        let ifd0 = &header.ifds[0];
+        let artist = parse_value(ifd0.entry(315).unwrap(), "Jonathan Q. Doe").unwrap();
+        assert!(patch_entry(&mut f, ifd0, 315, &artist).is_err());
+        assert!(parse_value(ifd0.entry(274).unwrap(), "six").is_err());
+    }
+}
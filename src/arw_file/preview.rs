@@ -0,0 +1,175 @@
+use std::fmt;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+
+use arw_file::header::Header;
+use arw_file::jpeg;
+use arw_file::read_at;
+
+const COMPRESSION: u16 = 259;
+const STRIP_OFFSETS: u16 = 273;
+const STRIP_BYTE_COUNTS: u16 = 279;
+const JPEG_INTERCHANGE_FORMAT: u16 = 513;
+const JPEG_INTERCHANGE_FORMAT_LENGTH: u16 = 514;
+// Sony MakerNote
+const PREVIEW_IMAGE: u16 = 0x2001;
+
+// Old-style and new-style JPEG compression
+const JPEG_COMPRESSIONS: [u32; 2] = [6, 7];
+
+#[derive(PartialEq, Debug)]
+pub enum JpegStatus {
+    Valid,
+    // SOI present, EOI missing or the end of the file reached first
+    Truncated,
+    // No SOI, or starting past the end of the file
+    Invalid,
+}
+
+pub struct EmbeddedJpeg {
+    pub label: String,
+    pub offset: u32,
+    pub length: u32,
+    // (width, height) from the SOF marker
+    pub dimensions: Option<(u16, u16)>,
+    pub status: JpegStatus,
+}
+
+impl fmt::Display for EmbeddedJpeg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let dimensions = match self.dimensions {
+            Some((width, height)) => format!("{}x{}", width, height),
+            None => String::from("unknown size"),
+        };
+        write!(f,
+               "{}, {}, offset: {}, length: {}, {:?}",
+               self.label,
+               dimensions,
+               self.offset,
+               self.length,
+               self.status)
+    }
+}
+
+// Thumbnail and preview JPEGs referenced from the IFD chain and the MakerNote
+pub fn embedded_jpegs<R: Read + Seek>(f: &mut R,
+                                      header: &Header)
+                                      -> io::Result<Vec<EmbeddedJpeg>> {
+    let mut locations: Vec<(String, u32, u32)> = vec![];
+
+    for (i, ifd) in header.ifds_of_type("Main").into_iter().enumerate() {
+        let first_value = |tag_id| {
+            ifd.entry(tag_id)
+                .and_then(|entry| entry.unsigned_values())
+                .and_then(|v| v.first().cloned())
+        };
+        let label = if i == 1 {
+            String::from("ThumbnailImage (IFD1)")
+        } else {
+            format!("PreviewImage (IFD{})", i)
+        };
+
+        if let (Some(offset), Some(length)) = (first_value(JPEG_INTERCHANGE_FORMAT),
+                                               first_value(JPEG_INTERCHANGE_FORMAT_LENGTH)) {
+            locations.push((label.clone(), offset, length));
+        }
+
+        let jpeg_strips = first_value(COMPRESSION).is_some_and(|c| JPEG_COMPRESSIONS.contains(&c));
+        let offsets = ifd.entry(STRIP_OFFSETS).and_then(|entry| entry.unsigned_values());
+        let counts = ifd.entry(STRIP_BYTE_COUNTS).and_then(|entry| entry.unsigned_values());
+        if let (true, Some(offsets), Some(counts)) = (jpeg_strips, offsets, counts) {
+            // A JPEG stored in strips is only extractable when written as a single strip
+            if offsets.len() == 1 && counts.len() == 1 {
+                locations.push((format!("{} strips", label), offsets[0], counts[0]));
+            }
+        }
+    }
+
+    if let Some(entry) = header.entry("MakerNote", PREVIEW_IMAGE) {
+        if entry.count > 4 {
+            locations.push((String::from("PreviewImage (MakerNote)"),
+                            entry.value_offset,
+                            entry.count));
+        }
+    }
+
+    let file_length = f.seek(SeekFrom::End(0))?;
+    let mut jpegs: Vec<EmbeddedJpeg> = vec![];
+
+    for (label, offset, length) in locations {
+        if length == 0 || jpegs.iter().any(|jpeg| jpeg.offset == offset) {
+            continue;
+        }
+        // A preview cut short by the end of the file is extracted as far as it goes
+        let available = file_length.saturating_sub(offset as u64).min(length as u64) as u32;
+        let jpeg = if available == 0 {
+            EmbeddedJpeg {
+                label,
+                offset,
+                length,
+                dimensions: None,
+                status: JpegStatus::Invalid,
+            }
+        } else {
+            let data = read_at(f, offset as u64, available as usize)?;
+            let status = match jpeg_status(&data) {
+                JpegStatus::Valid if available < length => JpegStatus::Truncated,
+                status => status,
+            };
+            EmbeddedJpeg {
+                label,
+                offset,
+                length: available,
+                dimensions: jpeg::dimensions(&data),
+                status,
+            }
+        };
+        jpegs.push(jpeg);
+    }
+
+    Ok(jpegs)
+}
+
+pub fn jpeg_status(data: &[u8]) -> JpegStatus {
+    if !jpeg::has_soi(data) {
+        JpegStatus::Invalid
+    } else if !jpeg::has_eoi(data) {
+        JpegStatus::Truncated
+    } else {
+        JpegStatus::Valid
+    }
+}
+
+pub fn read_jpeg<R: Read + Seek>(f: &mut R, jpeg: &EmbeddedJpeg) -> io::Result<Vec<u8>> {
+    read_at(f, jpeg.offset as u64, jpeg.length as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arw_file::byte_order::ByteOrder;
+    use arw_file::writer::{self, TiffIfd, Value};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_truncated_preview() {
+        let ifd0 = |offset| {
+            let mut ifd0 = TiffIfd::new();
+            ifd0.set(JPEG_INTERCHANGE_FORMAT, Value::Long(vec![offset]));
+            ifd0.set(JPEG_INTERCHANGE_FORMAT_LENGTH, Value::Long(vec![100]));
+            ifd0
+        };
+        // A JPEG said to be 100 bytes long, 6 of which are in the file
+        let offset = writer::encode(ByteOrder::LittleEndian, &[ifd0(0)]).len() as u32;
+        let mut data = writer::encode(ByteOrder::LittleEndian, &[ifd0(offset)]);
+        data.extend_from_slice(&[0xff, jpeg::SOI, 0xff, jpeg::EOI, 0, 0]);
+
+        let mut f = Cursor::new(data);
+        let header = Header::new(&mut f).unwrap();
+        let jpegs = embedded_jpegs(&mut f, &header).unwrap();
+        assert_eq!(jpegs.len(), 1);
+        assert_eq!(jpegs[0].status, JpegStatus::Truncated);
+        assert_eq!(jpegs[0].length, 6);
+        assert_eq!(read_jpeg(&mut f, &jpegs[0]).unwrap().len(), 6);
+    }
+}
@@ -0,0 +1,424 @@
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+
+use arw_file::header::Header;
+use arw_file::ifd::IFD;
+use arw_file::ljpeg;
+use arw_file::read_at;
+
+const NEW_SUBFILE_TYPE: u16 = 254;
+const IMAGE_WIDTH: u16 = 256;
+const IMAGE_LENGTH: u16 = 257;
+const BITS_PER_SAMPLE: u16 = 258;
+const COMPRESSION: u16 = 259;
+const PHOTOMETRIC_INTERPRETATION: u16 = 262;
+const STRIP_OFFSETS: u16 = 273;
+const ROWS_PER_STRIP: u16 = 278;
+const STRIP_BYTE_COUNTS: u16 = 279;
+const TILE_WIDTH: u16 = 322;
+const TILE_LENGTH: u16 = 323;
+const TILE_OFFSETS: u16 = 324;
+const TILE_BYTE_COUNTS: u16 = 325;
+const SONY_TONE_CURVE: u16 = 0x7010;
+
+const PHOTOMETRIC_CFA: u32 = 32803;
+const COMPRESSION_NONE: u32 = 1;
+const COMPRESSION_LOSSLESS_JPEG: u32 = 7;
+const COMPRESSION_SONY_ARW: u32 = 32767;
+
+// Sony raw data is always little endian, whatever the TIFF byte order
+fn le_u16(buf: &[u8]) -> u16 {
+    ((buf[1] as u16) << 8) + buf[0] as u16
+}
+
+fn le_u32(buf: &[u8]) -> u32 {
+    ((buf[3] as u32) << 24) + ((buf[2] as u32) << 16) + ((buf[1] as u32) << 8) + buf[0] as u32
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Undemosaiced sensor data, one sample per pixel, row by row
+pub struct CfaImage {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u16>,
+}
+
+pub struct RawIfd<'a> {
+    pub ifd: &'a IFD,
+    pub width: u32,
+    pub height: u32,
+    pub bits_per_sample: u32,
+    pub compression: u32,
+}
+
+impl<'a> RawIfd<'a> {
+    // The IFD holding the CFA data: PhotometricInterpretation CFA, or a full
+    // resolution (NewSubfileType 0) image with Sony compression
+    pub fn locate(header: &'a Header) -> Option<RawIfd<'a>> {
+        header.ifds
+            .iter()
+            .filter(|ifd| ifd.ifd_type != "MakerNote")
+            .find(|ifd| {
+                let photometric = first_value(ifd, PHOTOMETRIC_INTERPRETATION);
+                let compression = first_value(ifd, COMPRESSION);
+                let subfile_type = first_value(ifd, NEW_SUBFILE_TYPE).unwrap_or(0);
+                photometric == Some(PHOTOMETRIC_CFA) ||
+                (compression == Some(COMPRESSION_SONY_ARW) && subfile_type == 0)
+            })
+            .and_then(|ifd| {
+                match (first_value(ifd, IMAGE_WIDTH), first_value(ifd, IMAGE_LENGTH)) {
+                    (Some(width), Some(height)) => {
+                        Some(RawIfd {
+                            ifd,
+                            width,
+                            height,
+                            bits_per_sample: first_value(ifd, BITS_PER_SAMPLE).unwrap_or(16),
+                            compression: first_value(ifd, COMPRESSION).unwrap_or(COMPRESSION_NONE),
+                        })
+                    }
+                    _ => None,
+                }
+            })
+    }
+
+    pub fn strips(&self) -> Vec<(u32, u32)> {
+        let values = |tag_id| {
+            self.ifd.entry(tag_id).and_then(|e| e.unsigned_values()).unwrap_or_default()
+        };
+        let (offsets, counts) = (values(STRIP_OFFSETS), values(STRIP_BYTE_COUNTS));
+        offsets.into_iter().zip(counts).collect()
+    }
+
+    // (tile width, tile length, [(offset, byte count)]), strips being tiles
+    // spanning the image width
+    pub fn tiles(&self) -> (u32, u32, Vec<(u32, u32)>) {
+        let offsets = self.ifd.entry(TILE_OFFSETS).and_then(|e| e.unsigned_values());
+        let counts = self.ifd.entry(TILE_BYTE_COUNTS).and_then(|e| e.unsigned_values());
+        let tile_size = (first_value(self.ifd, TILE_WIDTH), first_value(self.ifd, TILE_LENGTH));
+        match (offsets, counts, tile_size) {
+            (Some(offsets), Some(counts), (Some(tile_width), Some(tile_length))) => {
+                (tile_width, tile_length, offsets.into_iter().zip(counts).collect())
+            }
+            _ => {
+                let rows_per_strip = first_value(self.ifd, ROWS_PER_STRIP).unwrap_or(self.height);
+                (self.width, rows_per_strip, self.strips())
+            }
+        }
+    }
+
+    fn strip_data<R: Read + Seek>(&self, f: &mut R) -> io::Result<Vec<u8>> {
+        let mut data = vec![];
+        for (offset, count) in self.strips() {
+            data.extend(read_at(f, offset as u64, count as usize)?);
+        }
+        Ok(data)
+    }
+}
+
+fn first_value(ifd: &IFD, tag_id: u16) -> Option<u32> {
+    ifd.entry(tag_id).and_then(|entry| entry.unsigned_values()).and_then(|v| v.first().cloned())
+}
+
+// Expands the four SonyToneCurve knee points into a lookup table for 12 bit
+// values, each segment doubling the slope of the previous one. Values reach
+// 16 bits, decode_arw2_row bringing them down to 14.
+pub fn tone_curve(points: &[u32]) -> Vec<u16> {
+    let mut knees: [usize; 6] = [0, 0, 0, 0, 0, 4095];
+    for (i, point) in points.iter().take(4).enumerate() {
+        knees[i + 1] = ((point >> 2) & 0xfff) as usize;
+    }
+
+    let mut curve: Vec<u16> = (0..0x1000).map(|i| i as u16).collect();
+    for i in 0..5 {
+        for j in (knees[i] + 1)..(knees[i + 1] + 1) {
+            curve[j] = curve[j - 1].saturating_add(1 << i);
+        }
+    }
+    curve
+}
+
+pub fn header_tone_curve(header: &Header, raw: &RawIfd) -> Vec<u16> {
+    let points = raw.ifd
+        .entry(SONY_TONE_CURVE)
+        .or_else(|| header.ifds.iter().filter_map(|ifd| ifd.entry(SONY_TONE_CURVE)).next())
+        .and_then(|entry| entry.unsigned_values())
+        .unwrap_or_default();
+    tone_curve(&points)
+}
+
+// One row of ARW2 data: blocks of 16 bytes, each holding 16 same-colour
+// pixels two columns apart as an 11 bit max and min, their positions, and
+// 14 deltas of 7 bits scaled to the max - min range
+pub fn decode_arw2_row(data: &[u8], curve: &[u16], out: &mut [u16]) {
+    let width = out.len();
+    let mut pix = [0u16; 16];
+    let mut col = 0;
+    let mut pos = 0;
+
+    while width > 30 && col < width - 30 && pos + 16 <= data.len() {
+        let block = &data[pos..pos + 16];
+        let val = le_u32(block);
+        let max = 0x7ff & val;
+        let min = 0x7ff & (val >> 11);
+        let imax = (0x0f & (val >> 22)) as usize;
+        let imin = (0x0f & (val >> 26)) as usize;
+
+        let mut sh = 0;
+        while sh < 4 && (0x80 << sh) <= max as i32 - min as i32 {
+            sh += 1;
+        }
+
+        let mut bit = 30;
+        for (i, p) in pix.iter_mut().enumerate() {
+            *p = if i == imax {
+                max as u16
+            } else if i == imin {
+                min as u16
+            } else {
+                let byte = bit >> 3;
+                let word = if byte + 1 < 16 {
+                    le_u16(&block[byte..byte + 2]) as u32
+                } else {
+                    block[byte] as u32
+                };
+                let value = (((word >> (bit & 7)) & 0x7f) << sh) + min;
+                bit += 7;
+                if value > 0x7ff { 0x7ff } else { value as u16 }
+            };
+        }
+
+        // The curve maps to 16 bits, samples are kept at 14 as dcraw does
+        for p in pix.iter() {
+            out[col] = curve[(*p as usize) << 1] >> 2;
+            col += 2;
+        }
+        col -= if col & 1 == 1 { 1 } else { 31 };
+        pos += 16;
+    }
+}
+
+// Copies a decoded tile into the image, clipping at the right and bottom
+// edges. Sony writes 4 component frames of half the tile size, one component
+// per pixel of each 2x2 CFA block; other writers fill tile rows in order.
+fn place_tile(tile: &ljpeg::LosslessJpeg,
+              pixels: &mut [u16],
+              image_size: (usize, usize),
+              tile_origin: (usize, usize),
+              tile_size: (usize, usize)) {
+    let (width, height) = image_size;
+    let (tile_x, tile_y) = tile_origin;
+    let (tile_width, tile_length) = tile_size;
+    let sony_layout = tile.components == 4 && tile.width * 2 == tile_width &&
+                      tile.height * 2 == tile_length;
+
+    for (i, sample) in tile.data.iter().enumerate() {
+        let (row, col) = if sony_layout {
+            let pixel = i / 4;
+            let component = i % 4;
+            (2 * (pixel / tile.width) + component / 2, 2 * (pixel % tile.width) + component % 2)
+        } else {
+            (i / tile_width, i % tile_width)
+        };
+        let (x, y) = (tile_x + col, tile_y + row);
+        if row < tile_length && x < width && y < height {
+            pixels[y * width + x] = *sample;
+        }
+    }
+}
+
+// The number of pixels of the raw image, refused when over `max`, the most
+// its data can hold, so that the image size alone cannot make it allocate
+fn pixel_count(raw: &RawIfd, max: u64) -> io::Result<usize> {
+    let count = raw.width as u64 * raw.height as u64;
+    if count == 0 {
+        return Err(invalid_data(String::from("Empty raw image")));
+    }
+    if count > max || count > usize::MAX as u64 {
+        return Err(invalid_data(format!("{}x{} image larger than its {} bytes of data",
+                                        raw.width,
+                                        raw.height,
+                                        max)));
+    }
+    Ok(count as usize)
+}
+
+fn decode_lossless_jpeg<R: Read + Seek>(f: &mut R, raw: &RawIfd) -> io::Result<Vec<u16>> {
+    let width = raw.width as usize;
+    let height = raw.height as usize;
+    let (tile_width, tile_length, tiles) = raw.tiles();
+    let (tile_width, tile_length) = (tile_width as usize, tile_length as usize);
+    if tile_width == 0 || tile_length == 0 {
+        return Err(invalid_data(String::from("Invalid tile size")));
+    }
+    let tiles_across = width.div_ceil(tile_width);
+
+    // At least a bit per sample in the tiles that are in the file
+    let file_length = f.seek(SeekFrom::End(0))?;
+    let tile_bytes = tiles.iter().map(|&(_, count)| count as u64).sum::<u64>().min(file_length);
+    let mut pixels = vec![0u16; pixel_count(raw, tile_bytes * 8)?];
+    for (i, (offset, count)) in tiles.into_iter().enumerate() {
+        let tile = ljpeg::decode(&read_at(f, offset as u64, count as usize)?)?;
+        place_tile(&tile,
+                   &mut pixels,
+                   (width, height),
+                   ((i % tiles_across) * tile_width, (i / tiles_across) * tile_length),
+                   (tile_width, tile_length));
+    }
+    Ok(pixels)
+}
+
+// Decodes the CFA data of the raw IFD, picking the decoder from its Compression
+pub fn decode<R: Read + Seek>(f: &mut R, header: &Header) -> io::Result<CfaImage> {
+    let raw = match RawIfd::locate(header) {
+        Some(raw) => raw,
+        None => return Err(invalid_data(String::from("No raw image IFD found"))),
+    };
+    let width = raw.width as usize;
+    let height = raw.height as usize;
+    if raw.compression == COMPRESSION_LOSSLESS_JPEG {
+        return Ok(CfaImage {
+            width: raw.width,
+            height: raw.height,
+            data: decode_lossless_jpeg(f, &raw)?,
+        });
+    }
+    let data = raw.strip_data(f)?;
+
+    // A byte per sample for ARW2, two uncompressed
+    let count = pixel_count(&raw, data.len() as u64)?;
+    let pixels = if raw.compression == COMPRESSION_SONY_ARW && data.len() < count * 2 {
+        let curve = header_tone_curve(header, &raw);
+        let mut pixels = vec![0u16; count];
+        for (row, out) in pixels.chunks_mut(width).enumerate() {
+            decode_arw2_row(&data[row * width..(row + 1) * width], &curve, out);
+        }
+        pixels
+    } else if (raw.compression == COMPRESSION_NONE || raw.compression == COMPRESSION_SONY_ARW) &&
+              data.len() / 2 >= count {
+        // Uncompressed, one little endian word per sample
+        data.chunks(2).take(count).map(le_u16).collect()
+    } else {
+        return Err(invalid_data(format!("Unsupported raw data: compression {}, {} bits, {} bytes \
+                                         for {}x{}",
+                                        raw.compression,
+                                        raw.bits_per_sample,
+                                        data.len(),
+                                        width,
+                                        height)));
+    };
+
+    Ok(CfaImage {
+        width: raw.width,
+        height: raw.height,
+        data: pixels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arw_file::byte_order::ByteOrder;
+    use arw_file::writer::{self, TiffIfd, Value};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_default_tone_curve() {
+        let curve = tone_curve(&[]);
+        assert_eq!(curve[0], 0);
+        assert_eq!(curve[1], 16);
+        // The largest 11 bit sample, 0x7ff, decodes to 16376
+        assert_eq!(curve[0x7ff << 1] >> 2, 16376);
+    }
+
+    #[test]
+    fn test_tone_curve_knees() {
+        // Knees at 8, 16, 24, 32 once shifted
+        let curve = tone_curve(&[32, 64, 96, 128]);
+        assert_eq!(curve[8], 8);
+        assert_eq!(curve[16], 24);
+        assert_eq!(curve[24], 56);
+        assert_eq!(curve[33], 120 + 16);
+    }
+
+    #[test]
+    fn test_place_sony_tile() {
+        // One 4 component sample per 2x2 block of a 4x2 tile
+        let tile = ljpeg::LosslessJpeg {
+            width: 2,
+            height: 1,
+            components: 4,
+            precision: 14,
+            data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+        };
+        let mut pixels = vec![0u16; 6 * 2];
+        place_tile(&tile, &mut pixels, (6, 2), (2, 0), (4, 2));
+        assert_eq!(pixels, vec![0, 0, 1, 2, 5, 6, 0, 0, 3, 4, 7, 8]);
+    }
+
+    // Two ARW2 blocks: max 100, min 10, max at pixel 1, min at pixel 0, all
+    // deltas 1
+    fn arw2_blocks() -> Vec<u8> {
+        let val: u32 = 100 | (10 << 11) | (1 << 22);
+        let mut block = [0u8; 16];
+        block[0..4].copy_from_slice(&[val as u8,
+                                      (val >> 8) as u8,
+                                      (val >> 16) as u8,
+                                      (val >> 24) as u8]);
+        let mut bit = 30;
+        for _ in 0..14 {
+            block[bit >> 3] |= (1 << (bit & 7)) as u8;
+            bit += 7;
+        }
+        let mut data = block.to_vec();
+        data.extend_from_slice(&block);
+        data
+    }
+
+    #[test]
+    fn test_decode_arw2_row() {
+        // Undoes the shift by 2 of decode_arw2_row
+        let curve: Vec<u16> = (0..0x1000).map(|i| (i << 1) as u16).collect();
+        let mut out = [0u16; 32];
+        decode_arw2_row(&arw2_blocks(), &curve, &mut out);
+        assert_eq!(out[0], 10);
+        assert_eq!(out[2], 100);
+        assert_eq!(out[4], 11);
+        assert_eq!(out[1], 10);
+        assert_eq!(out[31], 11);
+    }
+
+    #[test]
+    fn test_oversized_image() {
+        let mut ifd0 = TiffIfd::new();
+        ifd0.set(IMAGE_WIDTH, Value::Long(vec![0xffff_ffff]));
+        ifd0.set(IMAGE_LENGTH, Value::Long(vec![0xffff_ffff]));
+        ifd0.set(COMPRESSION, Value::Short(vec![COMPRESSION_SONY_ARW as u16]));
+        ifd0.set(STRIP_OFFSETS, Value::Offsets(vec![arw2_blocks()]));
+        ifd0.set(STRIP_BYTE_COUNTS, Value::Long(vec![32]));
+        let mut f = Cursor::new(writer::encode(ByteOrder::LittleEndian, &[ifd0]));
+        let header = Header::new(&mut f).unwrap();
+        assert!(decode(&mut f, &header).is_err());
+    }
+
+    #[test]
+    fn test_decode_arw2() {
+        let mut ifd0 = TiffIfd::new();
+        ifd0.set(IMAGE_WIDTH, Value::Long(vec![32]));
+        ifd0.set(IMAGE_LENGTH, Value::Long(vec![1]));
+        ifd0.set(COMPRESSION, Value::Short(vec![COMPRESSION_SONY_ARW as u16]));
+        ifd0.set(STRIP_OFFSETS, Value::Offsets(vec![arw2_blocks()]));
+        ifd0.set(STRIP_BYTE_COUNTS, Value::Long(vec![32]));
+        let mut f = Cursor::new(writer::encode(ByteOrder::LittleEndian, &[ifd0]));
+        let header = Header::new(&mut f).unwrap();
+
+        // 11 bit values on the 14 bit scale of the uncompressed data
+        let image = decode(&mut f, &header).unwrap();
+        assert_eq!(image.data[0], 80);
+        assert_eq!(image.data[2], 800);
+        assert_eq!(image.data[4], 88);
+        assert!(image.data.iter().all(|&sample| sample < 1 << 14));
+    }
+}
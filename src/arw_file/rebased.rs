@@ -0,0 +1,82 @@
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+// Shifts the positions of `inner` by `base`: offset n of the rebased reader
+// is offset n + base of `inner`. Lets the IFD parser, which seeks to absolute
+// offsets, read TIFF structures embedded in other files or held in memory.
+pub struct Rebased<R> {
+    inner: R,
+    base: i64,
+}
+
+impl<R: Read + Seek> Rebased<R> {
+    pub fn new(inner: R, base: i64) -> Rebased<R> {
+        Rebased { inner, base }
+    }
+
+    pub fn base(&self) -> i64 {
+        self.base
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+}
+
+impl<R: Read> Read for Rebased<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<W: Write> Write for Rebased<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<R: Seek> Seek for Rebased<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let inner_pos = match pos {
+            SeekFrom::Start(offset) => {
+                let target = offset as i64 + self.base;
+                if target < 0 {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                              "seek before the start of the rebased data"));
+                }
+                self.inner.seek(SeekFrom::Start(target as u64))?
+            }
+            other => self.inner.seek(other)?,
+        };
+        Ok((inner_pos as i64 - self.base) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_negative_base() {
+        // Data copied from offset 100 of some file
+        let mut rebased = Rebased::new(Cursor::new(vec![1, 2, 3, 4]), -100);
+        let mut buf = [0; 2];
+        assert_eq!(rebased.seek(SeekFrom::Start(102)).unwrap(), 102);
+        rebased.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [3, 4]);
+    }
+
+    #[test]
+    fn test_positive_base() {
+        let mut rebased = Rebased::new(Cursor::new(vec![0, 0, 7, 8]), 2);
+        let mut buf = [0; 2];
+        assert_eq!(rebased.seek(SeekFrom::Start(0)).unwrap(), 0);
+        rebased.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [7, 8]);
+    }
+}
@@ -0,0 +1,408 @@
+// Editable copy of a TIFF/ARW file. The IFD tree is rebuilt from the parsed
+// file with pointer entries turned into child IFDs and image data into data
+// blocks, so that entries can be added, changed or removed and the file
+// written out again with every value, IFD and data block relocated.
+use std::io;
+use std::io::{Read, Seek};
+
+use arw_file::byte_order::ByteOrder;
+use arw_file::header::Header;
+use arw_file::ifd::{IFD, IFDEntry};
+use arw_file::ifd::tag;
+use arw_file::limits::Budget;
+use arw_file::read_at;
+use arw_file::sr2;
+use arw_file::writer::{self, TiffIfd, Value};
+
+const MAKER_NOTE: u16 = 37500;
+// Offsets tag and byte counts tag of image data
+const DATA_TAGS: [(u16, u16); 3] = [(273, 279), (324, 325), (513, 514)];
+
+pub struct TiffFile {
+    pub byte_order: ByteOrder,
+    // The IFD0, IFD1, ... chain
+    pub ifds: Vec<TiffIfd>,
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// IFD type of the child IFDs a pointer entry leads to, as labelled by Header
+pub fn child_ifd_type(tag_id: u16) -> Option<String> {
+    match tag_id {
+        MAKER_NOTE => Some(String::from("MakerNote")),
+        sr2::DNG_PRIVATE_DATA => Some(String::from("SR2Private")),
+        sr2::SR2_SUB_IFD_OFFSET => Some(String::from("SR2SubIFD")),
+        _ => tag::TAGS.get(&tag_id).filter(|tag| tag.ifd).map(|tag| tag.label.clone()),
+    }
+}
+
+struct Reader<'a, R: 'a> {
+    f: &'a mut R,
+    header: &'a Header,
+    budget: Budget,
+    // Offsets of the IFDs read, a pointer back to one of them making a loop
+    visited: Vec<u32>,
+    // Copy MakerNotes that cannot be relocated as they are, for removal
+    opaque_maker_note: bool,
+}
+
+impl<'a, R: Read + Seek> Reader<'a, R> {
+    fn ifd(&mut self, offset: u32, ifd_type: &str) -> io::Result<IFD> {
+        if self.visited.contains(&offset) {
+            return Err(invalid_data(format!("{} at {} is already read, the IFDs loop",
+                                            ifd_type,
+                                            offset)));
+        }
+        self.visited.push(offset);
+        IFD::new(self.f, offset, &self.header.byte_order, ifd_type, &mut self.budget)
+    }
+
+    fn tiff_ifd(&mut self, ifd: &IFD) -> io::Result<TiffIfd> {
+        let mut tiff_ifd = TiffIfd::from_ifd(ifd);
+
+        for &(offsets_tag, counts_tag) in DATA_TAGS.iter() {
+            let offsets = ifd.entry(offsets_tag).and_then(|entry| entry.unsigned_values());
+            let counts = ifd.entry(counts_tag).and_then(|entry| entry.unsigned_values());
+            if let (Some(offsets), Some(counts)) = (offsets, counts) {
+                if offsets.len() != counts.len() {
+                    return Err(invalid_data(format!("{} offsets for {} byte counts of tag {}",
+                                                    offsets.len(),
+                                                    counts.len(),
+                                                    offsets_tag)));
+                }
+                let mut blocks = vec![];
+                for (offset, count) in offsets.into_iter().zip(counts) {
+                    // Image data is checked against the file only, not the limits
+                    let what = format!("Data of tag {}", offsets_tag);
+                    self.budget.check(&what, offset as u64, count as u64)?;
+                    blocks.push(read_at(self.f, offset as u64, count as usize)?);
+                }
+                tiff_ifd.set(offsets_tag, Value::Offsets(blocks));
+            }
+        }
+
+        for entry in ifd.entries.iter().filter(|entry| entry.is_ifd()) {
+            let ifd_type = entry.tag.label.clone();
+            if entry.tag.id == MAKER_NOTE {
+                let value = self.maker_note(ifd, entry)?;
+                tiff_ifd.set(MAKER_NOTE, value);
+                continue;
+            }
+            let offsets = entry.unsigned_values().unwrap_or_else(|| vec![entry.value_offset]);
+            let mut children = vec![];
+            for offset in offsets.into_iter().filter(|offset| *offset != 0) {
+                let child = self.ifd(offset, &ifd_type)?;
+                // Children are written as an array, with nothing after them
+                if child.next_ifd_offset != 0 {
+                    return Err(invalid_data(format!("{} at {} chains to another IFD at {}, \
+                                                     which cannot be rewritten",
+                                                    ifd_type,
+                                                    offset,
+                                                    child.next_ifd_offset)));
+                }
+                children.push(self.tiff_ifd(&child)?);
+            }
+            tiff_ifd.set(entry.tag.id, Value::Ifds(children));
+        }
+        Ok(tiff_ifd)
+    }
+
+    // Sony maker notes, header-less or after a "SONY DSC \0\0\0" header, are
+    // rebuilt, their offsets being file offsets. Others would point at the
+    // wrong data once moved, and are only copied when they are to be removed.
+    fn maker_note(&mut self, parent: &IFD, entry: &IFDEntry) -> io::Result<Value> {
+        let byte_order = self.header.byte_order;
+        let (header, bytes) = entry.value_bytes.split_at(entry.maker_note_header_length());
+        let fits = bytes.len() >= 2 &&
+                   2 + 12 * byte_order.parse_u16(&bytes[0..2]) as usize <= bytes.len();
+        if fits && parent.ifd_type == "Exif IFD" {
+            let maker_note = self.ifd(entry.ifd_offset(), "MakerNote")?;
+            return Ok(Value::MakerNote(header.to_vec(),
+                                       Box::new(TiffIfd::from_ifd(&maker_note))));
+        }
+        if self.opaque_maker_note {
+            return Ok(Value::Undefined(entry.value_bytes.clone()));
+        }
+        Err(invalid_data(format!("MakerNote at {} of an unknown layout cannot be moved",
+                                 entry.value_offset)))
+    }
+
+    // Sony DNGPrivateData points to the SR2Private IFD
+    fn sr2_private(&mut self) -> io::Result<Option<TiffIfd>> {
+        let private = match sr2::sr2_private(self.f, self.header) {
+            Some(private) => private,
+            None => return Ok(None),
+        };
+        let mut tiff_ifd = TiffIfd::from_ifd(&private);
+        if let Some(key) = sr2::first_value(&private, sr2::SR2_SUB_IFD_KEY) {
+            if let Some(sub_ifd) = sr2::sr2_sub_ifd(self.f, self.header) {
+                tiff_ifd.set(sr2::SR2_SUB_IFD_OFFSET,
+                             Value::Enciphered(Box::new(TiffIfd::from_ifd(&sub_ifd)), key));
+            }
+        }
+        Ok(Some(tiff_ifd))
+    }
+}
+
+impl TiffFile {
+    pub fn read<R: Read + Seek>(f: &mut R, header: &Header) -> io::Result<TiffFile> {
+        TiffFile::read_with(f, header, false)
+    }
+
+    // As read, a MakerNote that cannot be relocated being copied as it is
+    // rather than an error, for callers that remove it
+    pub fn read_opaque_maker_note<R: Read + Seek>(f: &mut R,
+                                                  header: &Header)
+                                                  -> io::Result<TiffFile> {
+        TiffFile::read_with(f, header, true)
+    }
+
+    fn read_with<R: Read + Seek>(f: &mut R,
+                                 header: &Header,
+                                 opaque_maker_note: bool)
+                                 -> io::Result<TiffFile> {
+        let budget = Budget::new(f, header.limits)?;
+        let mut reader = Reader {
+            f,
+            header,
+            budget,
+            visited: vec![],
+            opaque_maker_note,
+        };
+
+        let mut ifds = vec![];
+        let mut offset = header.ifd_offset;
+        while offset != 0 {
+            let ifd = reader.ifd(offset, "Main")?;
+            ifds.push(reader.tiff_ifd(&ifd)?);
+            offset = ifd.next_ifd_offset;
+        }
+
+        // DNGPrivateData is copied as read, its offset going stale once moved,
+        // unless it is the SR2Private IFD, which is rebuilt
+        let private = reader.sr2_private()?;
+        if let Some(ifd0) = ifds.first_mut() {
+            match private {
+                Some(private) => ifd0.set(sr2::DNG_PRIVATE_DATA, Value::Ifds(vec![private])),
+                None => {
+                    ifd0.remove(sr2::DNG_PRIVATE_DATA);
+                }
+            }
+        }
+
+        Ok(TiffFile {
+            byte_order: header.byte_order,
+            ifds,
+        })
+    }
+
+    // The index-th IFD of the given type, in IFD chain then depth first order
+    pub fn ifd_mut(&mut self, ifd_type: &str, index: usize) -> Option<&mut TiffIfd> {
+        let mut skip = index;
+        for ifd in &mut self.ifds {
+            if let Some(found) = find_ifd(ifd, "Main", ifd_type, &mut skip) {
+                return Some(found);
+            }
+        }
+        None
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        writer::encode(self.byte_order, &self.ifds)
+    }
+}
+
+fn find_ifd<'a>(ifd: &'a mut TiffIfd,
+                own_type: &str,
+                ifd_type: &str,
+                skip: &mut usize)
+                -> Option<&'a mut TiffIfd> {
+    if own_type == ifd_type {
+        if *skip == 0 {
+            return Some(ifd);
+        }
+        *skip -= 1;
+    }
+    for &mut (tag_id, ref mut value) in &mut ifd.entries {
+        let child_type = match child_ifd_type(tag_id) {
+            Some(child_type) => child_type,
+            None => continue,
+        };
+        let children: Vec<&mut TiffIfd> = match *value {
+            Value::Ifds(ref mut children) => children.iter_mut().collect(),
+            Value::MakerNote(_, ref mut child) |
+            Value::Enciphered(ref mut child, _) => vec![&mut **child],
+            _ => vec![],
+        };
+        for child in children {
+            if let Some(found) = find_ifd(child, &child_type, ifd_type, skip) {
+                return Some(found);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arw_file::header::Header;
+    use std::io::Cursor;
+
+    fn sample() -> Vec<u8> {
+        let mut exif = TiffIfd::new();
+        exif.set(33434, Value::Rational(vec![(1, 250)]));
+        let mut ifd0 = TiffIfd::new();
+        ifd0.set(271, Value::Ascii(String::from("SONY")));
+        ifd0.set(279, Value::Long(vec![4]));
+        ifd0.set(273, Value::Offsets(vec![vec![1, 2, 3, 4]]));
+        ifd0.set(34665, Value::Ifds(vec![exif]));
+        writer::encode(ByteOrder::LittleEndian, &[ifd0])
+    }
+
+    #[test]
+    fn test_edit_and_relocate() {
+        let mut f = Cursor::new(sample());
+        let header = Header::new(&mut f).unwrap();
+        let mut tiff = TiffFile::read(&mut f, &header).unwrap();
+
+        tiff.ifd_mut("Main", 0)
+            .unwrap()
+            .set(315, Value::Ascii(String::from("A much longer artist name")));
+        tiff.ifd_mut("Exif IFD", 0).unwrap().remove(33434);
+        tiff.ifd_mut("Exif IFD", 0).unwrap().set(34855, Value::Short(vec![100]));
+        assert!(tiff.ifd_mut("GPS IFD", 0).is_none());
+
+        let mut f = Cursor::new(tiff.to_bytes());
+        let header = Header::new(&mut f).unwrap();
+        let artist = header.entry("Main", 315).unwrap();
+        assert_eq!(artist.ascii_value().unwrap(), "A much longer artist name\0");
+        assert!(header.entry("Exif IFD", 33434).is_none());
+        assert_eq!(header.entry("Exif IFD", 34855).unwrap().short_values(), Some(vec![100]));
+
+        let strip = header.entry("Main", 273).unwrap().value_offset;
+        assert_eq!(read_at(&mut f, strip as u64, 4).unwrap(), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_sr2_sub_ifd_relocated() {
+        let mut sub_ifd = TiffIfd::new();
+        sub_ifd.set(0x7313, Value::Short(vec![2400, 1024, 1024, 1600]));
+        let mut private = TiffIfd::new();
+        private.set(sr2::SR2_SUB_IFD_OFFSET, Value::Enciphered(Box::new(sub_ifd), 0x1234));
+        private.set(sr2::SR2_SUB_IFD_LENGTH, Value::Long(vec![0]));
+        private.set(sr2::SR2_SUB_IFD_KEY, Value::Long(vec![0x1234]));
+        let mut ifd0 = TiffIfd::new();
+        ifd0.set(271, Value::Ascii(String::from("SONY")));
+        ifd0.set(sr2::DNG_PRIVATE_DATA, Value::Ifds(vec![private]));
+        let mut f = Cursor::new(writer::encode(ByteOrder::BigEndian, &[ifd0]));
+        let header = Header::new(&mut f).unwrap();
+
+        // Grows IFD0 so that everything after it moves
+        let mut tiff = TiffFile::read(&mut f, &header).unwrap();
+        tiff.ifd_mut("Main", 0).unwrap().set(315, Value::Ascii(String::from("Someone")));
+        let mut f = Cursor::new(tiff.to_bytes());
+        let header = Header::new(&mut f).unwrap();
+
+        let sub_ifd = sr2::sr2_sub_ifd(&mut f, &header).unwrap();
+        assert_eq!(sub_ifd.entry(0x7313).unwrap().short_values(),
+                   Some(vec![2400, 1024, 1024, 1600]));
+    }
+
+    #[test]
+    fn test_prefixed_maker_note_relocated() {
+        let mut sony = TiffIfd::new();
+        sony.set(0xb001, Value::Short(vec![358]));
+        sony.set(0x2031, Value::Ascii(String::from("1234567")));
+        let mut exif = TiffIfd::new();
+        exif.set(MAKER_NOTE, Value::MakerNote(b"SONY DSC \0\0\0".to_vec(), Box::new(sony)));
+        let mut ifd0 = TiffIfd::new();
+        ifd0.set(34665, Value::Ifds(vec![exif]));
+        let mut f = Cursor::new(writer::encode(ByteOrder::LittleEndian, &[ifd0]));
+        let header = Header::new(&mut f).unwrap();
+        assert!(header.entry("MakerNote", 0xb001).is_some());
+
+        let mut tiff = TiffFile::read(&mut f, &header).unwrap();
+        tiff.ifd_mut("Main", 0).unwrap().set(315, Value::Ascii(String::from("Someone")));
+        let mut f = Cursor::new(tiff.to_bytes());
+        let header = Header::new(&mut f).unwrap();
+        let maker_note = header.entry("Exif IFD", MAKER_NOTE).unwrap();
+        assert!(maker_note.value_bytes.starts_with(b"SONY DSC "));
+        assert_eq!(header.entry("MakerNote", 0x2031).unwrap().ascii_value().unwrap(),
+                   "1234567\0");
+    }
+
+    #[test]
+    fn test_unknown_maker_note() {
+        // An IFD of 3 entries said to fit in 20 bytes
+        let mut maker_note = vec![3, 0];
+        maker_note.extend_from_slice(&[0; 18]);
+        let mut exif = TiffIfd::new();
+        exif.set(MAKER_NOTE, Value::Undefined(maker_note));
+        // XPComment, read as the rest of the 3 entries
+        exif.set(40092, Value::Byte(vec![0; 40]));
+        let mut ifd0 = TiffIfd::new();
+        ifd0.set(34665, Value::Ifds(vec![exif]));
+        let mut f = Cursor::new(writer::encode(ByteOrder::LittleEndian, &[ifd0]));
+        let header = Header::new(&mut f).unwrap();
+
+        assert!(TiffFile::read(&mut f, &header).is_err());
+        let mut tiff = TiffFile::read_opaque_maker_note(&mut f, &header).unwrap();
+        assert!(tiff.ifd_mut("Exif IFD", 0).unwrap().remove(MAKER_NOTE).is_some());
+    }
+
+    #[test]
+    fn test_sub_ifd_loop() {
+        let mut child = TiffIfd::new();
+        child.set(254, Value::Long(vec![1]));
+        let mut ifd0 = TiffIfd::new();
+        ifd0.set(330, Value::Ifds(vec![child]));
+        let mut data = writer::encode(ByteOrder::LittleEndian, &[ifd0]);
+        let header = Header::new(&mut Cursor::new(&data)).unwrap();
+
+        // The only entry, SubIFDs, pointing back to IFD0
+        let pos = header.ifd_offset as usize + 2 + 8;
+        data.copy_within(4..8, pos);
+        let error = TiffFile::read(&mut Cursor::new(&data), &header).err().unwrap();
+        assert!(error.to_string().contains("loop"));
+    }
+
+    #[test]
+    fn test_foreign_dng_private_data_removed() {
+        let mut ifd0 = TiffIfd::new();
+        ifd0.set(271, Value::Ascii(String::from("SONY")));
+        ifd0.set(sr2::DNG_PRIVATE_DATA, Value::Byte(b"Adobe\0MakN\0\0\0\0".to_vec()));
+        let mut f = Cursor::new(writer::encode(ByteOrder::LittleEndian, &[ifd0]));
+        let header = Header::new(&mut f).unwrap();
+
+        let tiff = TiffFile::read(&mut f, &header).unwrap();
+        let header = Header::new(&mut Cursor::new(tiff.to_bytes())).unwrap();
+        assert!(header.entry("Main", sr2::DNG_PRIVATE_DATA).is_none());
+        assert!(header.entry("Main", 271).is_some());
+    }
+
+    #[test]
+    fn test_chained_sub_ifds() {
+        let mut child = TiffIfd::new();
+        child.set(254, Value::Long(vec![1]));
+        let mut exif = TiffIfd::new();
+        exif.set(33434, Value::Rational(vec![(1, 250)]));
+        let mut ifd0 = TiffIfd::new();
+        ifd0.set(330, Value::Ifds(vec![child]));
+        ifd0.set(34665, Value::Ifds(vec![exif]));
+        let mut data = writer::encode(ByteOrder::LittleEndian, &[ifd0]);
+        let header = Header::new(&mut Cursor::new(&data)).unwrap();
+        let child = header.ifds_of_type("SubIFDs")[0].offset as usize;
+        let exif = header.ifds_of_type("Exif IFD")[0].offset;
+
+        // The SubIFD chaining to the Exif IFD
+        data[child + 14..child + 18].copy_from_slice(&exif.to_le_bytes());
+        let header = Header::new(&mut Cursor::new(&data)).unwrap();
+        assert_eq!(header.ifds[1].next_ifd_offset, exif);
+
+        let error = TiffFile::read(&mut Cursor::new(&data), &header).err().unwrap();
+        assert!(error.to_string().contains("chains"));
+    }
+}
@@ -0,0 +1,93 @@
+// Serde representation of value bytes: a lowercase hex string in human
+// readable formats (JSON), a byte string in binary ones (MessagePack, CBOR)
+use std::fmt;
+
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::Serializer;
+
+pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    if serializer.is_human_readable() {
+        let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        serializer.serialize_str(&hex)
+    } else {
+        serializer.serialize_bytes(bytes)
+    }
+}
+
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a hex string or a byte string")
+    }
+
+    fn visit_str<E: de::Error>(self, hex: &str) -> Result<Vec<u8>, E> {
+        if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+            return Err(E::invalid_value(de::Unexpected::Str(hex), &self));
+        }
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| E::invalid_value(de::Unexpected::Str(hex), &self))
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Vec<u8>, E> {
+        Ok(bytes.to_vec())
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, bytes: Vec<u8>) -> Result<Vec<u8>, E> {
+        Ok(bytes)
+    }
+
+    // Formats without a byte string type write a sequence of integers
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(bytes)
+    }
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    if deserializer.is_human_readable() {
+        deserializer.deserialize_str(BytesVisitor)
+    } else {
+        deserializer.deserialize_byte_buf(BytesVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arw_file::byte_order::ByteOrder;
+    use arw_file::header::Header;
+    use arw_file::writer::{self, TiffIfd, Value};
+    use serde_json;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_header_round_trip() {
+        let mut ifd = TiffIfd::new();
+        ifd.set(271, Value::Ascii(String::from("SONY")));
+        ifd.set(33437, Value::Rational(vec![(28, 10)]));
+        let mut f = Cursor::new(writer::encode(ByteOrder::BigEndian, &[ifd]));
+        let header = Header::new(&mut f).unwrap();
+
+        let json = serde_json::to_string(&header).unwrap();
+        assert!(json.contains("\"byte_order\":\"BigEndian\""));
+        assert!(json.contains("\"field_type\":5,\"count\":1"));
+        assert!(json.contains("\"value_bytes\":\"0000001c0000000a\""));
+
+        let parsed: Header = serde_json::from_str(&json).unwrap();
+        let entry = parsed.entry("Main", 33437).unwrap();
+        assert_eq!(entry.tag.label, "FNumber");
+        assert_eq!(entry.field_type.name(), "RATIONAL");
+        assert_eq!(entry.string_value(), "14/5");
+        assert_eq!(parsed.entry("Main", 271).and_then(|e| e.ascii_value()),
+                   Some(String::from("SONY\0")));
+        assert!(serde_json::from_str::<Header>(&json.replace("0000001c", "0000001")).is_err());
+    }
+}
@@ -0,0 +1,272 @@
+// XMP sidecars: generated from the TIFF, Exif and Sony tags, and read back
+// and merged with the embedded XMP packet
+use std::path::Path;
+
+use num::integer;
+
+use arw_file::header::Header;
+use arw_file::ifd::IFDEntry;
+use arw_file::raw::RawIfd;
+use arw_file::xmp::{self, Property};
+
+pub const NAMESPACES: [(&str, &str); 6] =
+    [("tiff", "http://ns.adobe.com/tiff/1.0/"),
+     ("exif", "http://ns.adobe.com/exif/1.0/"),
+     ("aux", "http://ns.adobe.com/exif/1.0/aux/"),
+     ("xmp", "http://ns.adobe.com/xap/1.0/"),
+     ("dc", "http://purl.org/dc/elements/1.1/"),
+     ("crs", "http://ns.adobe.com/camera-raw-settings/1.0/")];
+
+// IFD type, tag id and XMP property
+const PROPERTIES: [(&str, u16, &str); 24] =
+    [("Main", 271, "tiff:Make"),
+     ("Main", 272, "tiff:Model"),
+     ("Main", 274, "tiff:Orientation"),
+     ("Main", 305, "tiff:Software"),
+     ("Main", 315, "tiff:Artist"),
+     ("Main", 33432, "tiff:Copyright"),
+     ("Main", 306, "xmp:ModifyDate"),
+     ("Exif IFD", 33434, "exif:ExposureTime"),
+     ("Exif IFD", 33437, "exif:FNumber"),
+     ("Exif IFD", 34850, "exif:ExposureProgram"),
+     ("Exif IFD", 34855, "exif:ISOSpeedRatings"),
+     ("Exif IFD", 36867, "exif:DateTimeOriginal"),
+     ("Exif IFD", 36868, "exif:DateTimeDigitized"),
+     ("Exif IFD", 37377, "exif:ShutterSpeedValue"),
+     ("Exif IFD", 37378, "exif:ApertureValue"),
+     ("Exif IFD", 37380, "exif:ExposureBiasValue"),
+     ("Exif IFD", 37381, "exif:MaxApertureValue"),
+     ("Exif IFD", 37383, "exif:MeteringMode"),
+     ("Exif IFD", 37386, "exif:FocalLength"),
+     ("Exif IFD", 41987, "exif:WhiteBalance"),
+     ("Exif IFD", 41989, "exif:FocalLengthIn35mmFilm"),
+     ("Exif IFD", 42033, "aux:SerialNumber"),
+     ("Exif IFD", 42036, "aux:Lens"),
+     ("MakerNote", 0xb027, "aux:LensID")];
+
+// Array typed properties, written as rdf:Seq even with one item
+const SEQUENCES: [&str; 1] = ["exif:ISOSpeedRatings"];
+
+pub fn namespace(prefix: &str) -> Option<&'static str> {
+    NAMESPACES.iter().find(|&&(p, _)| p == prefix).map(|&(_, namespace)| namespace)
+}
+
+// image.ARW -> image.xmp, as Adobe tools name sidecars
+pub fn sidecar_path(filename: &str) -> String {
+    Path::new(filename).with_extension("xmp").to_string_lossy().into_owned()
+}
+
+// "YYYY:MM:DD HH:MM:SS" -> "YYYY-MM-DDTHH:MM:SS"
+fn iso_date(date: &str) -> String {
+    let bytes = date.as_bytes();
+    if bytes.len() >= 19 && bytes[4] == b':' && bytes[7] == b':' && bytes[10] == b' ' {
+        format!("{}-{}-{}T{}", &date[0..4], &date[5..7], &date[8..10], &date[11..19])
+    } else {
+        String::from(date)
+    }
+}
+
+// "n/d" in lowest terms, "n" for whole numbers. A zero denominator is kept
+// as read, so 0/0 gives "0/0".
+fn rational(numerator: i64, denominator: i64) -> String {
+    let divisor = integer::gcd(numerator, denominator);
+    if divisor == 0 || denominator == 0 {
+        return format!("{}/{}", numerator, denominator);
+    }
+    let (numerator, denominator) = (numerator / divisor, denominator / divisor);
+    if denominator == 1 {
+        numerator.to_string()
+    } else {
+        format!("{}/{}", numerator, denominator)
+    }
+}
+
+fn entry_values(entry: &IFDEntry) -> Vec<String> {
+    fn strings<T: ToString>(values: Vec<T>) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+    match entry.field_type.name() {
+        "ASCII" => {
+            let value = entry.ascii_value().unwrap_or_default();
+            vec![String::from(value.trim_end_matches('\0').trim())]
+        }
+        "RATIONAL" | "SRATIONAL" => {
+            let signed = entry.field_type.name() == "SRATIONAL";
+            entry.value_bytes
+                .chunks(8)
+                .filter(|pair| pair.len() == 8)
+                .map(|pair| {
+                    let order = entry.byte_order;
+                    if signed {
+                        rational(order.parse_i32(&pair[0..4]) as i64,
+                                 order.parse_i32(&pair[4..8]) as i64)
+                    } else {
+                        rational(order.parse_u32(&pair[0..4]) as i64,
+                                 order.parse_u32(&pair[4..8]) as i64)
+                    }
+                })
+                .collect()
+        }
+        "SSHORT" => strings(entry.signed_short_values().unwrap_or_default()),
+        "SLONG" => strings(entry.signed_long_values().unwrap_or_default()),
+        _ => strings(entry.unsigned_values().unwrap_or_default()),
+    }
+}
+
+fn property(name: &str, values: Vec<String>) -> Property {
+    let prefix = name.split(':').next().unwrap_or("");
+    let is_array = values.len() != 1 || SEQUENCES.contains(&name);
+    Property {
+        name: String::from(name),
+        namespace: String::from(namespace(prefix).unwrap_or("")),
+        values,
+        array: if is_array { Some(String::from("Seq")) } else { None },
+    }
+}
+
+// Properties for a sidecar, from the tags of the parsed file
+pub fn properties(header: &Header) -> Vec<Property> {
+    let mut properties = vec![];
+    for &(ifd_type, tag_id, name) in PROPERTIES.iter() {
+        let entry = match header.entry(ifd_type, tag_id) {
+            Some(entry) => entry,
+            None => continue,
+        };
+        let mut values = entry_values(entry);
+        if name.contains("Date") {
+            values = values.iter().map(|date| iso_date(date)).collect();
+        }
+        if values.is_empty() || values.iter().all(|v| v.is_empty()) {
+            continue;
+        }
+        properties.push(property(name, values));
+    }
+    if let Some(raw) = RawIfd::locate(header) {
+        properties.push(property("tiff:ImageWidth", vec![raw.width.to_string()]));
+        properties.push(property("tiff:ImageLength", vec![raw.height.to_string()]));
+    }
+    properties
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// An XMP packet holding simple and array properties. Struct fields and
+// array items flattened into paths are left out.
+pub fn packet(properties: &[Property]) -> String {
+    let properties: Vec<&Property> = properties.iter()
+        .filter(|p| !p.name.contains('/') && !p.name.contains('[') && p.name.contains(':'))
+        .collect();
+
+    let mut prefixes: Vec<(&str, &str)> = vec![];
+    for p in &properties {
+        let prefix = p.name.split(':').next().unwrap_or("");
+        if !prefixes.iter().any(|&(known, _)| known == prefix) {
+            prefixes.push((prefix, &p.namespace));
+        }
+    }
+
+    let mut xml = format!("<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n\
+                           <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n \
+                           <rdf:RDF xmlns:rdf=\"{}\">\n  \
+                           <rdf:Description rdf:about=\"\"",
+                          xmp::RDF_NS);
+    for &(prefix, namespace) in &prefixes {
+        xml.push_str(&format!("\n    xmlns:{}=\"{}\"", prefix, escape(namespace)));
+    }
+
+    let (simple, arrays): (Vec<&Property>, Vec<&Property>) = properties.iter()
+        .partition(|p| p.array.is_none() && p.values.len() == 1);
+    for p in &simple {
+        xml.push_str(&format!("\n   {}=\"{}\"", p.name, escape(&p.values[0])));
+    }
+    xml.push_str(">\n");
+    for p in &arrays {
+        // Read back as is, the language of alternatives aside: the first is
+        // written as the default one
+        let array = p.array.as_ref().map_or("Seq", |array| array.as_str());
+        xml.push_str(&format!("   <{}>\n    <rdf:{}>\n", p.name, array));
+        for (i, value) in p.values.iter().enumerate() {
+            let lang = if array == "Alt" && i == 0 { " xml:lang=\"x-default\"" } else { "" };
+            xml.push_str(&format!("     <rdf:li{}>{}</rdf:li>\n", lang, escape(value)));
+        }
+        xml.push_str(&format!("    </rdf:{}>\n   </{}>\n", array, p.name));
+    }
+    xml.push_str("  </rdf:Description>\n </rdf:RDF>\n</x:xmpmeta>\n<?xpacket end=\"w\"?>\n");
+    xml
+}
+
+// Embedded properties merged with the sidecar ones, the sidecar value of a
+// property present in both winning when `prefer_sidecar` is set
+pub fn merge(embedded: Vec<Property>,
+             sidecar: Vec<Property>,
+             prefer_sidecar: bool)
+             -> Vec<Property> {
+    let mut merged = embedded;
+    for property in sidecar {
+        match merged.iter().position(|p| p.name == property.name) {
+            Some(i) if prefer_sidecar => merged[i] = property,
+            Some(_) => {}
+            None => merged.push(property),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packet_round_trip() {
+        let properties = vec![property("tiff:Make", vec![String::from("SONY")]),
+                              property("exif:ISOSpeedRatings", vec![String::from("100")]),
+                              property("exif:DateTimeOriginal",
+                                       vec![iso_date("2019:05:04 10:11:12")]),
+                              property("aux:Lens", vec![String::from("FE 24-70mm F2.8 GM & co")])];
+        let parsed = xmp::parse(packet(&properties).as_bytes()).unwrap();
+        assert_eq!(parsed.len(), 4);
+        for property in &properties {
+            assert!(parsed.contains(property));
+        }
+        let date = parsed.iter().find(|p| p.name == "exif:DateTimeOriginal").unwrap();
+        assert_eq!(date.values, vec!["2019-05-04T10:11:12"]);
+    }
+
+    #[test]
+    fn test_array_kinds_round_trip() {
+        let mut subject = property("dc:subject", vec![String::from("beach"), String::from("sun")]);
+        subject.array = Some(String::from("Bag"));
+        let mut title = property("dc:title", vec![String::from("Holiday")]);
+        title.array = Some(String::from("Alt"));
+        let properties = vec![property("xmp:Rating", vec![String::from("3")]), subject, title];
+
+        let packet = packet(&properties);
+        assert!(packet.contains("<rdf:li xml:lang=\"x-default\">Holiday</rdf:li>"));
+        assert_eq!(xmp::parse(packet.as_bytes()).unwrap(), properties);
+    }
+
+    #[test]
+    fn test_merge() {
+        let embedded = vec![property("xmp:Rating", vec![String::from("2")]),
+                            property("tiff:Make", vec![String::from("SONY")])];
+        let sidecar = vec![property("xmp:Rating", vec![String::from("5")]),
+                           property("dc:subject", vec![String::from("beach")])];
+
+        let merged = merge(embedded.clone(), sidecar.clone(), false);
+        assert_eq!(merged.len(), 3);
+        assert_eq!(merged[0].values, vec!["2"]);
+        let merged = merge(embedded, sidecar, true);
+        assert_eq!(merged[0].values, vec!["5"]);
+    }
+
+    #[test]
+    fn test_rational() {
+        assert_eq!(rational(28, 10), "14/5");
+        assert_eq!(rational(4, 1), "4");
+        assert_eq!(rational(-1, 3), "-1/3");
+        assert_eq!(rational(0, 0), "0/0");
+        assert_eq!(rational(5, 0), "5/0");
+    }
+}
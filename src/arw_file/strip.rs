@@ -0,0 +1,100 @@
+// Privacy stripping: location, serial numbers and owner data
+use arw_file::rewrite::TiffFile;
+
+const MAKER_NOTE: u16 = 37500;
+
+// IFD type, tag id and name of the entries removed. XMP and IPTC packets go
+// whole, as they repeat the location, serial numbers and names.
+const PRIVATE_TAGS: [(&str, u16, &str); 13] =
+    [("Main", 34853, "GPS IFD"),
+     ("Main", 315, "Artist"),
+     ("Main", 33432, "Copyright"),
+     ("Main", 50735, "CameraSerialNumber"),
+     ("Main", 700, "XMP"),
+     ("Main", 33723, "IPTC-NAA"),
+     // Photoshop image resources, holding IPTC data
+     ("Main", 34377, "PhotoshopSettings"),
+     ("Exif IFD", 42016, "ImageUniqueID"),
+     ("Exif IFD", 42032, "CameraOwnerName"),
+     ("Exif IFD", 42033, "BodySerialNumber"),
+     ("Exif IFD", 42037, "LensSerialNumber"),
+     ("MakerNote", 0x2031, "SerialNumber"),
+     // Enciphered, holds InternalSerialNumber
+     ("MakerNote", 0x9050, "Tag9050")];
+
+pub struct Removed {
+    pub ifd_type: String,
+    pub tag_id: u16,
+    pub name: String,
+}
+
+fn remove(tiff: &mut TiffFile, ifd_type: &str, tag_id: u16, name: &str) -> Vec<Removed> {
+    let mut removed = vec![];
+    let mut index = 0;
+    while let Some(ifd) = tiff.ifd_mut(ifd_type, index) {
+        if ifd.remove(tag_id).is_some() {
+            removed.push(Removed {
+                ifd_type: String::from(ifd_type),
+                tag_id,
+                name: String::from(name),
+            });
+        }
+        index += 1;
+    }
+    removed
+}
+
+// Removes the private entries, and the MakerNote if asked to, returning
+// what was removed
+pub fn strip(tiff: &mut TiffFile, maker_note: bool) -> Vec<Removed> {
+    let mut removed = vec![];
+    for &(ifd_type, tag_id, name) in PRIVATE_TAGS.iter() {
+        removed.extend(remove(tiff, ifd_type, tag_id, name));
+    }
+    if maker_note {
+        removed.extend(remove(tiff, "Exif IFD", MAKER_NOTE, "MakerNote"));
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arw_file::byte_order::ByteOrder;
+    use arw_file::header::Header;
+    use arw_file::writer::{self, TiffIfd, Value};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_strip() {
+        let mut gps = TiffIfd::new();
+        gps.set(1, Value::Ascii(String::from("N")));
+        let mut maker_note = TiffIfd::new();
+        maker_note.set(0x2031, Value::Ascii(String::from("1234567")));
+        maker_note.set(0xb001, Value::Short(vec![358]));
+        let mut exif = TiffIfd::new();
+        exif.set(42033, Value::Ascii(String::from("7654321")));
+        exif.set(MAKER_NOTE, Value::MakerNote(vec![], Box::new(maker_note)));
+        let mut ifd0 = TiffIfd::new();
+        ifd0.set(271, Value::Ascii(String::from("SONY")));
+        ifd0.set(700, Value::Byte(b"<x:xmpmeta/>".to_vec()));
+        ifd0.set(33723, Value::Undefined(vec![0x1c, 2, 80, 0, 3, b'B', b'o', b'b']));
+        ifd0.set(34665, Value::Ifds(vec![exif]));
+        ifd0.set(34853, Value::Ifds(vec![gps]));
+        let mut f = Cursor::new(writer::encode(ByteOrder::LittleEndian, &[ifd0]));
+        let header = Header::new(&mut f).unwrap();
+        let mut tiff = TiffFile::read(&mut f, &header).unwrap();
+
+        let removed: Vec<String> = strip(&mut tiff, false).into_iter().map(|r| r.name).collect();
+        assert_eq!(removed,
+                   vec!["GPS IFD", "XMP", "IPTC-NAA", "BodySerialNumber", "SerialNumber"]);
+
+        let mut f = Cursor::new(tiff.to_bytes());
+        let header = Header::new(&mut f).unwrap();
+        assert!(header.ifds_of_type("GPS IFD").is_empty());
+        assert!(header.entry("Main", 700).is_none());
+        assert!(header.entry("Main", 33723).is_none());
+        assert!(header.entry("MakerNote", 0x2031).is_none());
+        assert!(header.entry("MakerNote", 0xb001).is_some());
+    }
+}
@@ -0,0 +1,438 @@
+// Conformance checks against TIFF 6.0 and Exif 2.3: header, entry order and
+// alignment, field types and counts of the common tags, required tags, data
+// inside the file and not overlapping
+use std::fmt;
+use std::io::{self, Write};
+
+use arw_file::exiftool;
+use arw_file::header::Header;
+use arw_file::ifd::{IFD, IFDEntry};
+use arw_file::layout;
+
+#[derive(PartialEq, PartialOrd, Clone, Copy, Debug)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+pub struct Finding {
+    pub severity: Severity,
+    // Relative to the TIFF header
+    pub offset: u64,
+    pub message: String,
+}
+
+// Allowed types and count (None for any) of a tag, by group: "" for IFD0,
+// IFD1 and the SubIFDs, then "Exif", "GPS" and "Interop"
+type Spec = (&'static str, u16, &'static [&'static str], Option<u32>);
+
+const SHORT_OR_LONG: &[&str] = &["SHORT", "LONG"];
+const POINTER: &[&str] = &["LONG", "IFD"];
+
+const SPECS: [Spec; 63] = [("", 254, &["LONG"], Some(1)),
+                           ("", 256, SHORT_OR_LONG, Some(1)),
+                           ("", 257, SHORT_OR_LONG, Some(1)),
+                           ("", 258, &["SHORT"], None),
+                           ("", 259, &["SHORT"], Some(1)),
+                           ("", 262, &["SHORT"], Some(1)),
+                           ("", 270, &["ASCII"], None),
+                           ("", 271, &["ASCII"], None),
+                           ("", 272, &["ASCII"], None),
+                           ("", 273, SHORT_OR_LONG, None),
+                           ("", 274, &["SHORT"], Some(1)),
+                           ("", 277, &["SHORT"], Some(1)),
+                           ("", 278, SHORT_OR_LONG, Some(1)),
+                           ("", 279, SHORT_OR_LONG, None),
+                           ("", 282, &["RATIONAL"], Some(1)),
+                           ("", 283, &["RATIONAL"], Some(1)),
+                           ("", 284, &["SHORT"], Some(1)),
+                           ("", 296, &["SHORT"], Some(1)),
+                           ("", 305, &["ASCII"], None),
+                           ("", 306, &["ASCII"], Some(20)),
+                           ("", 315, &["ASCII"], None),
+                           ("", 322, SHORT_OR_LONG, Some(1)),
+                           ("", 323, SHORT_OR_LONG, Some(1)),
+                           ("", 324, &["LONG"], None),
+                           ("", 325, SHORT_OR_LONG, None),
+                           ("", 330, POINTER, None),
+                           ("", 513, &["LONG"], Some(1)),
+                           ("", 514, &["LONG"], Some(1)),
+                           ("", 531, &["SHORT"], Some(1)),
+                           ("", 33432, &["ASCII"], None),
+                           ("", 34665, POINTER, Some(1)),
+                           ("", 34853, POINTER, Some(1)),
+                           ("Exif", 33434, &["RATIONAL"], Some(1)),
+                           ("Exif", 33437, &["RATIONAL"], Some(1)),
+                           ("Exif", 34850, &["SHORT"], Some(1)),
+                           ("Exif", 34855, &["SHORT"], None),
+                           ("Exif", 36864, &["UNDEFINED"], Some(4)),
+                           ("Exif", 36867, &["ASCII"], Some(20)),
+                           ("Exif", 36868, &["ASCII"], Some(20)),
+                           ("Exif", 37121, &["UNDEFINED"], Some(4)),
+                           ("Exif", 37377, &["SRATIONAL"], Some(1)),
+                           ("Exif", 37378, &["RATIONAL"], Some(1)),
+                           ("Exif", 37380, &["SRATIONAL"], Some(1)),
+                           ("Exif", 37381, &["RATIONAL"], Some(1)),
+                           ("Exif", 37383, &["SHORT"], Some(1)),
+                           ("Exif", 37384, &["SHORT"], Some(1)),
+                           ("Exif", 37385, &["SHORT"], Some(1)),
+                           ("Exif", 37386, &["RATIONAL"], Some(1)),
+                           ("Exif", 37500, &["UNDEFINED"], None),
+                           ("Exif", 37510, &["UNDEFINED"], None),
+                           ("Exif", 40960, &["UNDEFINED"], Some(4)),
+                           ("Exif", 40961, &["SHORT"], Some(1)),
+                           ("Exif", 40962, SHORT_OR_LONG, Some(1)),
+                           ("Exif", 40963, SHORT_OR_LONG, Some(1)),
+                           ("Exif", 40965, POINTER, Some(1)),
+                           ("Exif", 42034, &["RATIONAL"], Some(4)),
+                           ("GPS", 0, &["BYTE"], Some(4)),
+                           ("GPS", 1, &["ASCII"], Some(2)),
+                           ("GPS", 2, &["RATIONAL"], Some(3)),
+                           ("GPS", 3, &["ASCII"], Some(2)),
+                           ("GPS", 4, &["RATIONAL"], Some(3)),
+                           ("GPS", 29, &["ASCII"], Some(11)),
+                           ("Interop", 1, &["ASCII"], Some(4))];
+
+// Tags an IFD of the group must hold, "Image" standing for any IFD with
+// strips or tiles
+const REQUIRED: [(&str, u16, &str, Severity); 14] =
+    [("IFD0", 282, "XResolution", Severity::Warning),
+     ("IFD0", 283, "YResolution", Severity::Warning),
+     ("IFD0", 296, "ResolutionUnit", Severity::Warning),
+     ("Exif", 36864, "ExifVersion", Severity::Error),
+     ("Exif", 40960, "FlashpixVersion", Severity::Warning),
+     ("Exif", 40961, "ColorSpace", Severity::Warning),
+     ("GPS", 0, "GPSVersionID", Severity::Error),
+     ("Interop", 1, "InteropIndex", Severity::Error),
+     ("Image", 256, "ImageWidth", Severity::Error),
+     ("Image", 257, "ImageLength", Severity::Error),
+     ("Image", 262, "PhotometricInterpretation", Severity::Error),
+     ("Image", 282, "XResolution", Severity::Warning),
+     ("Image", 283, "YResolution", Severity::Warning),
+     ("Image", 296, "ResolutionUnit", Severity::Warning)];
+
+const IFD_TYPE: u16 = 13;
+const STRIP_OFFSETS: u16 = 273;
+const STRIP_BYTE_COUNTS: u16 = 279;
+const TILE_OFFSETS: u16 = 324;
+const TILE_BYTE_COUNTS: u16 = 325;
+
+// The SPECS and REQUIRED group of an IFD, None for MakerNotes
+fn spec_group(group: &str) -> Option<&'static str> {
+    match group {
+        "ExifIFD" => Some("Exif"),
+        "GPS" => Some("GPS"),
+        "InteropIFD" => Some("Interop"),
+        group if exiftool::is_maker_notes(group) => None,
+        _ => Some(""),
+    }
+}
+
+fn type_name(entry: &IFDEntry) -> &str {
+    if entry.field_type.id() == IFD_TYPE { "IFD" } else { entry.field_type.name() }
+}
+
+struct Validator {
+    findings: Vec<Finding>,
+    // Bytes from the TIFF header to the end of the file
+    length: u64,
+}
+
+impl Validator {
+    fn report(&mut self, severity: Severity, offset: u64, message: String) {
+        self.findings.push(Finding {
+            severity,
+            offset,
+            message,
+        });
+    }
+
+    fn check_entry(&mut self, group: &str, offset: u64, entry: &IFDEntry) {
+        let name = exiftool::tag_name(group, entry);
+        if entry.field_type.name() == "Unknown" && entry.field_type.id() != IFD_TYPE {
+            let message = format!("[{}] {}: unknown field type {}",
+                                  group,
+                                  name,
+                                  entry.field_type.id());
+            self.report(Severity::Error, offset, message);
+        }
+        // Values that could not be read are the header's skipped parts
+        if entry.value_bytes.len() > 4 && entry.value_offset % 2 == 1 {
+            let message = format!("[{}] {}: value at odd offset {}",
+                                  group,
+                                  name,
+                                  entry.value_offset);
+            self.report(Severity::Warning, offset, message);
+        }
+
+        let spec_group = match spec_group(group) {
+            Some(spec_group) => spec_group,
+            None => return,
+        };
+        let spec = SPECS.iter().find(|spec| spec.0 == spec_group && spec.1 == entry.tag.id);
+        if let Some(&(_, _, types, count)) = spec {
+            if !types.contains(&type_name(entry)) {
+                let message = format!("[{}] {}: type {} instead of {}",
+                                      group,
+                                      name,
+                                      type_name(entry),
+                                      types.join(" or "));
+                self.report(Severity::Error, offset, message);
+            }
+            match count {
+                Some(count) if count != entry.count => {
+                    let message = format!("[{}] {}: count {} instead of {}",
+                                          group,
+                                          name,
+                                          entry.count,
+                                          count);
+                    self.report(Severity::Warning, offset, message);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Strip or tile offsets and byte counts: as many of each, data in the file
+    fn check_blocks(&mut self, group: &str, ifd: &IFD, kind: &str, tags: (u16, u16)) {
+        let values = |tag_id| ifd.entry(tag_id).and_then(|entry| entry.unsigned_values());
+        let (offsets, counts) = match (values(tags.0), values(tags.1)) {
+            (None, None) => return,
+            (Some(offsets), Some(counts)) => (offsets, counts),
+            (Some(_), None) | (None, Some(_)) => {
+                let message = format!("[{}] {} offsets or byte counts missing", group, kind);
+                return self.report(Severity::Error, ifd.offset as u64, message);
+            }
+        };
+        if offsets.len() != counts.len() {
+            let message = format!("[{}] {} {} offsets for {} byte counts",
+                                  group,
+                                  offsets.len(),
+                                  kind,
+                                  counts.len());
+            self.report(Severity::Error, ifd.offset as u64, message);
+        }
+        for (i, (offset, count)) in offsets.into_iter().zip(counts).enumerate() {
+            let end = offset as u64 + count as u64;
+            if end > self.length {
+                let message = format!("[{}] {} {} at {}-{} past the end of the file",
+                                      group,
+                                      kind,
+                                      i,
+                                      offset,
+                                      end);
+                self.report(Severity::Error, offset as u64, message);
+            }
+        }
+    }
+
+    fn check_ifd(&mut self, group: &str, ifd: &IFD) {
+        let offset = ifd.offset as u64;
+        if offset % 2 == 1 {
+            self.report(Severity::Warning, offset, format!("[{}] IFD at odd offset", group));
+        }
+        if ifd.entries_count == 0 {
+            self.report(Severity::Error, offset, format!("[{}] IFD without entries", group));
+        }
+        for (i, entry) in ifd.entries.iter().enumerate() {
+            let entry_offset = offset + 2 + 12 * i as u64;
+            if i > 0 {
+                let previous = ifd.entries[i - 1].tag.id;
+                if entry.tag.id == previous {
+                    let message = format!("[{}] tag {:#06x} repeated", group, entry.tag.id);
+                    self.report(Severity::Error, entry_offset, message);
+                } else if entry.tag.id < previous {
+                    let message = format!("[{}] tag {:#06x} after {:#06x}, entries not sorted",
+                                          group,
+                                          entry.tag.id,
+                                          previous);
+                    self.report(Severity::Error, entry_offset, message);
+                }
+            }
+            self.check_entry(group, entry_offset, entry);
+        }
+
+        let spec_group = match spec_group(group) {
+            Some(spec_group) => spec_group,
+            None => return,
+        };
+        self.check_blocks(group, ifd, "strip", (STRIP_OFFSETS, STRIP_BYTE_COUNTS));
+        self.check_blocks(group, ifd, "tile", (TILE_OFFSETS, TILE_BYTE_COUNTS));
+        let has_image = [STRIP_OFFSETS, TILE_OFFSETS].iter().any(|&tag| ifd.entry(tag).is_some());
+        // Tags reported missing, as IFD0 and image rules overlap
+        let mut missing = vec![];
+        for &(required_group, tag, name, severity) in REQUIRED.iter() {
+            let applies = required_group == spec_group || required_group == group ||
+                          (required_group == "Image" && has_image);
+            if applies && ifd.entry(tag).is_none() && !missing.contains(&tag) {
+                missing.push(tag);
+                self.report(severity, offset, format!("[{}] no {}", group, name));
+            }
+        }
+    }
+}
+
+// Findings on the file whose TIFF structure is `header`, parsed leniently,
+// its IFDs named as in `groups`, `length` bytes long from the TIFF header on
+pub fn validate(header: &Header, groups: &[(String, &IFD)], length: u64) -> Vec<Finding> {
+    let mut validator = Validator {
+        findings: vec![],
+        length,
+    };
+    if header.magic_number != 42 {
+        let message = format!("Magic number {} instead of 42", header.magic_number);
+        validator.report(Severity::Error, 2, message);
+    }
+    if header.ifd_offset % 2 == 1 {
+        validator.report(Severity::Warning, 4, String::from("First IFD at odd offset"));
+    }
+    for (offset, reason) in &header.skipped {
+        validator.report(Severity::Error, *offset, format!("{}, skipped", reason));
+    }
+    for (group, ifd) in groups {
+        validator.check_ifd(group, ifd);
+    }
+    let regions = layout::regions(groups);
+    for (first, second) in layout::overlaps(&regions) {
+        let message = format!("{} overlaps {} at {}-{}",
+                              second.label,
+                              first.label,
+                              second.start,
+                              first.end().min(second.end()));
+        validator.report(Severity::Error, second.start, message);
+    }
+    validator.findings.sort_by_key(|finding| finding.offset);
+    validator.findings
+}
+
+// A summary line, then the findings at absolute offsets (the TIFF header
+// being at `base`)
+pub fn pretty_print<W: Write>(out: &mut W,
+                              filename: &str,
+                              findings: &[Finding],
+                              base: u64)
+                              -> io::Result<()> {
+    let count = |severity| findings.iter().filter(|f| f.severity == severity).count();
+    let (errors, warnings) = (count(Severity::Error), count(Severity::Warning));
+    if findings.is_empty() {
+        return writeln!(out, "{}: OK", filename);
+    }
+    writeln!(out, "{}: {} errors, {} warnings", filename, errors, warnings)?;
+    for finding in findings {
+        writeln!(out,
+                 "  {:<7} {:>10}  {}",
+                 finding.severity,
+                 base + finding.offset,
+                 finding.message)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arw_file::byte_order::ByteOrder;
+    use arw_file::limits::Limits;
+    use arw_file::writer::{self, TiffIfd, Value};
+    use std::io::Cursor;
+
+    fn messages(data: &[u8]) -> Vec<String> {
+        let header = Header::lenient(&mut Cursor::new(data), Limits::default()).unwrap();
+        validate(&header, &exiftool::groups(&header, &[]), data.len() as u64)
+            .iter()
+            .map(|f| format!("{} {}", f.severity, f.message))
+            .collect()
+    }
+
+    #[test]
+    fn test_valid() {
+        let mut exif = TiffIfd::new();
+        exif.set(36864, Value::Undefined(b"0230".to_vec()));
+        exif.set(40960, Value::Undefined(b"0100".to_vec()));
+        exif.set(40961, Value::Short(vec![1]));
+        let mut ifd0 = TiffIfd::new();
+        ifd0.set(282, Value::Rational(vec![(72, 1)]));
+        ifd0.set(283, Value::Rational(vec![(72, 1)]));
+        ifd0.set(296, Value::Short(vec![2]));
+        ifd0.set(34665, Value::Ifds(vec![exif]));
+        let data = writer::encode(ByteOrder::LittleEndian, &[ifd0]);
+        assert_eq!(messages(&data), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_findings() {
+        let mut ifd0 = TiffIfd::new();
+        ifd0.set(274, Value::Long(vec![1]));
+        ifd0.set(306, Value::Ascii(String::from("2024:01:01")));
+        ifd0.set(273, Value::Long(vec![1000]));
+        ifd0.set(279, Value::Long(vec![16]));
+        let mut data = writer::encode(ByteOrder::LittleEndian, &[ifd0]);
+        // Swap the first two entries, StripOffsets and Orientation
+        let (first, second) = (10, 22);
+        let entry: Vec<u8> = data[first..first + 12].to_vec();
+        data.copy_within(second..second + 12, first);
+        data[second..second + 12].copy_from_slice(&entry);
+
+        let messages = messages(&data);
+        assert!(messages.contains(&String::from("error [IFD0] Orientation: type LONG instead of \
+                                                 SHORT")));
+        assert!(messages.contains(&String::from("error [IFD0] tag 0x0111 after 0x0112, \
+                                                 entries not sorted")));
+        assert!(messages.contains(&String::from("warning [IFD0] ModifyDate: count 11 instead \
+                                                 of 20")));
+        assert!(messages.contains(&String::from("error [IFD0] strip 0 at 1000-1016 past the end \
+                                                 of the file")));
+        assert!(messages.contains(&String::from("error [IFD0] no ImageWidth")));
+        assert!(messages.contains(&String::from("error [IFD0] no ImageLength")));
+        assert!(messages.contains(&String::from("error [IFD0] no PhotometricInterpretation")));
+        assert_eq!(messages.iter().filter(|m| m.ends_with("no XResolution")).count(), 1);
+        assert!(messages.contains(&String::from("warning [IFD0] no XResolution")));
+    }
+
+    #[test]
+    fn test_image_ifds() {
+        let mut image = TiffIfd::new();
+        image.set(256, Value::Long(vec![2]));
+        image.set(257, Value::Long(vec![1]));
+        image.set(262, Value::Short(vec![32803]));
+        image.set(273, Value::Offsets(vec![vec![0; 4]]));
+        image.set(279, Value::Long(vec![4]));
+        let mut ifd0 = TiffIfd::new();
+        ifd0.set(282, Value::Rational(vec![(72, 1)]));
+        ifd0.set(283, Value::Rational(vec![(72, 1)]));
+        ifd0.set(296, Value::Short(vec![2]));
+        ifd0.set(330, Value::Ifds(vec![image]));
+        let data = writer::encode(ByteOrder::LittleEndian, &[ifd0]);
+
+        assert_eq!(messages(&data),
+                   vec!["warning [SubIFD] no XResolution",
+                        "warning [SubIFD] no YResolution",
+                        "warning [SubIFD] no ResolutionUnit"]);
+    }
+
+    #[test]
+    fn test_value_past_the_end() {
+        let mut ifd0 = TiffIfd::new();
+        ifd0.set(270, Value::Ascii(String::from("a long description")));
+        ifd0.set(271, Value::Ascii(String::from("SONY")));
+        let mut data = writer::encode(ByteOrder::LittleEndian, &[ifd0]);
+        // Point ImageDescription past the end
+        data[18..22].copy_from_slice(&1000u32.to_le_bytes());
+
+        assert!(Header::new(&mut Cursor::new(&data)).is_err());
+        let messages = messages(&data);
+        assert!(messages.contains(&format!("error ImageDescription value at 1000 (19 bytes) is \
+                                            past the end of the file ({} bytes), skipped",
+                                           data.len())));
+        assert!(!messages.iter().any(|m| m.contains("Make")));
+    }
+}
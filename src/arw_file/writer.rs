@@ -0,0 +1,252 @@
+// Serializes IFDs into a TIFF structure, laying out out-of-line values and
+// data blocks after each IFD table
+use arw_file::byte_order::ByteOrder;
+use arw_file::ifd::IFD;
+use arw_file::sr2;
+
+pub enum Value {
+    Byte(Vec<u8>),
+    Ascii(String),
+    Short(Vec<u16>),
+    Long(Vec<u32>),
+    Rational(Vec<(u32, u32)>),
+    Undefined(Vec<u8>),
+    SLong(Vec<i32>),
+    SRational(Vec<(i32, i32)>),
+    // Already encoded in the output byte order: field type, count, bytes
+    Raw(u16, u32, Vec<u8>),
+    // Data blocks (strips, tiles, JPEGs) placed by the writer, stored as LONG offsets
+    Offsets(Vec<Vec<u8>>),
+    // Child IFDs (Exif, GPS, SubIFDs), stored as LONG offsets
+    Ifds(Vec<TiffIfd>),
+    // An IFD after a header (empty for none) written as an UNDEFINED block,
+    // offsets inside it being file offsets
+    MakerNote(Vec<u8>, Box<TiffIfd>),
+    // Sony SR2SubIFD: written like a MakerNote, then enciphered with the key.
+    // Stored as a LONG offset, its length going in the SR2SubIFDLength entry
+    Enciphered(Box<TiffIfd>, u32),
+}
+
+impl Value {
+    pub fn field_type(&self) -> u16 {
+        match *self {
+            Value::Byte(_) => 1,
+            Value::Ascii(_) => 2,
+            Value::Short(_) => 3,
+            Value::Long(_) | Value::Offsets(_) | Value::Ifds(_) | Value::Enciphered(..) => 4,
+            Value::Rational(_) => 5,
+            Value::Undefined(_) | Value::MakerNote(..) => 7,
+            Value::SLong(_) => 9,
+            Value::SRational(_) => 10,
+            Value::Raw(field_type, _, _) => field_type,
+        }
+    }
+
+    pub fn count(&self) -> u32 {
+        (match *self {
+            Value::Byte(ref v) | Value::Undefined(ref v) => v.len(),
+            // NUL terminated
+            Value::Ascii(ref s) => s.len() + 1,
+            Value::Short(ref v) => v.len(),
+            Value::Long(ref v) => v.len(),
+            Value::Rational(ref v) => v.len(),
+            Value::SLong(ref v) => v.len(),
+            Value::SRational(ref v) => v.len(),
+            Value::Offsets(ref v) => v.len(),
+            Value::Ifds(ref v) => v.len(),
+            Value::Enciphered(..) => 1,
+            // Known once written
+            Value::MakerNote(..) => 0,
+            Value::Raw(_, count, _) => count as usize,
+        }) as u32
+    }
+
+    pub fn bytes(&self, byte_order: ByteOrder) -> Vec<u8> {
+        let mut bytes = vec![];
+        match *self {
+            Value::Byte(ref v) | Value::Undefined(ref v) => bytes.extend_from_slice(v),
+            Value::Raw(_, _, ref v) => bytes.extend_from_slice(v),
+            Value::Ascii(ref s) => {
+                bytes.extend_from_slice(s.as_bytes());
+                bytes.push(0);
+            }
+            Value::Short(ref v) => {
+                for value in v {
+                    bytes.extend_from_slice(&byte_order.u16_to_slice(*value));
+                }
+            }
+            Value::Long(ref v) => {
+                for value in v {
+                    bytes.extend_from_slice(&byte_order.u32_to_slice(*value));
+                }
+            }
+            Value::SLong(ref v) => {
+                for value in v {
+                    bytes.extend_from_slice(&byte_order.u32_to_slice(*value as u32));
+                }
+            }
+            Value::Rational(ref v) => {
+                for &(numerator, denominator) in v {
+                    bytes.extend_from_slice(&byte_order.u32_to_slice(numerator));
+                    bytes.extend_from_slice(&byte_order.u32_to_slice(denominator));
+                }
+            }
+            Value::SRational(ref v) => {
+                for &(numerator, denominator) in v {
+                    bytes.extend_from_slice(&byte_order.u32_to_slice(numerator as u32));
+                    bytes.extend_from_slice(&byte_order.u32_to_slice(denominator as u32));
+                }
+            }
+            Value::Offsets(_) | Value::Ifds(_) | Value::MakerNote(..) | Value::Enciphered(..) => {
+                panic!("Offsets are resolved by the encoder")
+            }
+        }
+        bytes
+    }
+}
+
+#[derive(Default)]
+pub struct TiffIfd {
+    pub entries: Vec<(u16, Value)>,
+}
+
+impl TiffIfd {
+    pub fn new() -> TiffIfd {
+        TiffIfd { entries: vec![] }
+    }
+
+    // Copies the entries of a parsed IFD as they are encoded in the source,
+    // leaving out pointers to other IFDs for the caller to rebuild
+    pub fn from_ifd(ifd: &IFD) -> TiffIfd {
+        TiffIfd {
+            entries: ifd.entries
+                .iter()
+                .filter(|entry| !entry.is_ifd())
+                .map(|entry| {
+                    (entry.tag.id,
+                     Value::Raw(entry.field_type.id(), entry.count, entry.value_bytes.clone()))
+                })
+                .collect(),
+        }
+    }
+
+    // Adds an entry, replacing any entry with the same tag
+    pub fn set(&mut self, tag: u16, value: Value) {
+        self.remove(tag);
+        self.entries.push((tag, value));
+    }
+
+    pub fn remove(&mut self, tag: u16) -> Option<Value> {
+        match self.entries.iter().position(|&(t, _)| t == tag) {
+            Some(i) => Some(self.entries.remove(i).1),
+            None => None,
+        }
+    }
+}
+
+struct Encoder {
+    byte_order: ByteOrder,
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    // Values and IFDs start on a word boundary
+    fn append(&mut self, data: &[u8]) -> u32 {
+        if self.buf.len() % 2 == 1 {
+            self.buf.push(0);
+        }
+        let offset = self.buf.len() as u32;
+        self.buf.extend_from_slice(data);
+        offset
+    }
+
+    fn put(&mut self, pos: usize, data: &[u8]) {
+        self.buf[pos..pos + data.len()].copy_from_slice(data);
+    }
+
+    fn write_ifd(&mut self, ifd: &TiffIfd) -> u32 {
+        let mut entries: Vec<&(u16, Value)> = ifd.entries.iter().collect();
+        entries.sort_by_key(|&&(tag, _)| tag);
+
+        let table = self.append(&vec![0; 2 + 12 * entries.len() + 4]) as usize;
+        let count = self.byte_order.u16_to_slice(entries.len() as u16);
+        self.put(table, &count);
+        let mut enciphered_length = None;
+
+        for (i, &&(tag, ref value)) in entries.iter().enumerate() {
+            let mut count = value.count();
+            let mut field_type = value.field_type();
+            let bytes = match *value {
+                Value::Offsets(ref blocks) => {
+                    let offsets: Vec<u32> = blocks.iter().map(|block| self.append(block)).collect();
+                    Value::Long(offsets).bytes(self.byte_order)
+                }
+                Value::Ifds(ref ifds) => {
+                    let offsets: Vec<u32> = ifds.iter().map(|ifd| self.write_ifd(ifd)).collect();
+                    Value::Long(offsets).bytes(self.byte_order)
+                }
+                Value::MakerNote(ref header, ref ifd) => {
+                    // The header, IFD and values are written contiguously
+                    let offset = self.append(header);
+                    self.write_ifd(ifd);
+                    count = self.buf.len() as u32 - offset;
+                    self.byte_order.u32_to_slice(offset).to_vec()
+                }
+                Value::Enciphered(ref ifd, key) => {
+                    let offset = self.write_ifd(ifd);
+                    // Whole words only are enciphered
+                    while self.buf.len() % 4 != offset as usize % 4 {
+                        self.buf.push(0);
+                    }
+                    sr2::decrypt(&mut self.buf[offset as usize..], key);
+                    enciphered_length = Some(self.buf.len() as u32 - offset);
+                    self.byte_order.u32_to_slice(offset).to_vec()
+                }
+                Value::Long(_) | Value::Raw(..) if tag == sr2::SR2_SUB_IFD_LENGTH &&
+                                                  enciphered_length.is_some() => {
+                    count = 1;
+                    field_type = 4;
+                    Value::Long(enciphered_length.into_iter().collect()).bytes(self.byte_order)
+                }
+                _ => value.bytes(self.byte_order),
+            };
+            let pos = table + 2 + 12 * i;
+            let tag = self.byte_order.u16_to_slice(tag);
+            let field_type = self.byte_order.u16_to_slice(field_type);
+            let count = self.byte_order.u32_to_slice(count);
+            self.put(pos, &tag);
+            self.put(pos + 2, &field_type);
+            self.put(pos + 4, &count);
+            if bytes.len() <= 4 {
+                self.put(pos + 8, &bytes);
+            } else {
+                let offset = self.append(&bytes);
+                let offset = self.byte_order.u32_to_slice(offset);
+                self.put(pos + 8, &offset);
+            }
+        }
+        table as u32
+    }
+}
+
+// A complete TIFF file holding the given chain of IFDs
+pub fn encode(byte_order: ByteOrder, ifds: &[TiffIfd]) -> Vec<u8> {
+    let mut encoder = Encoder {
+        byte_order,
+        buf: vec![],
+    };
+    let magic: &[u8] = if byte_order == ByteOrder::LittleEndian { b"II" } else { b"MM" };
+    encoder.buf.extend_from_slice(magic);
+    encoder.buf.extend_from_slice(&byte_order.u16_to_slice(42));
+    encoder.buf.extend_from_slice(&[0; 4]);
+
+    // Offset of the pointer to patch with the next IFD offset
+    let mut pointer = 4;
+    for ifd in ifds {
+        let offset = encoder.write_ifd(ifd);
+        let offset_bytes = byte_order.u32_to_slice(offset);
+        encoder.put(pointer, &offset_bytes);
+        pointer = offset as usize + 2 + 12 * ifd.entries.len();
+    }
+    encoder.buf
+}
@@ -0,0 +1,438 @@
+// XMP packets (tag 700): a minimal XML reader, enough for RDF/XML as written
+// by cameras and raw developers, and the flattening of the RDF tree into
+// namespaced properties.
+use std::fmt;
+use std::io;
+
+use arw_file::header::Header;
+
+pub const XMP: u16 = 700;
+
+pub const RDF_NS: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+const XML_NS: &str = "http://www.w3.org/XML/1998/namespace";
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Property {
+    // Prefixed name, struct fields and array items as path segments:
+    // dc:subject, crs:Look/crs:Name, xmpMM:History[1]/stEvt:action
+    pub name: String,
+    pub namespace: String,
+    // Array items, or a single value
+    pub values: Vec<String>,
+    // "Bag", "Seq" or "Alt" for arrays of simple items, None otherwise
+    pub array: Option<String>,
+}
+
+impl fmt::Display for Property {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.values.join(", "))
+    }
+}
+
+struct Element {
+    // As written, prefix included
+    name: String,
+    namespace: String,
+    local: String,
+    // Name, namespace, value
+    attributes: Vec<(String, String, String)>,
+    children: Vec<Element>,
+    text: String,
+}
+
+impl Element {
+    fn is_rdf(&self, local: &str) -> bool {
+        self.namespace == RDF_NS && self.local == local
+    }
+
+    fn rdf_attribute(&self, local: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|&(name, namespace, _)| {
+                namespace == RDF_NS && name.rsplit(':').next() == Some(local)
+            })
+            .map(|(_, _, value)| value.as_ref())
+    }
+
+    // Attributes holding property values, as opposed to RDF syntax
+    fn value_attributes(&self) -> Vec<&(String, String, String)> {
+        self.attributes
+            .iter()
+            .filter(|&(_, namespace, _)| namespace != RDF_NS && namespace != XML_NS)
+            .collect()
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Invalid XMP: {}", message))
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+        let entity = &rest[1..end];
+        let character = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => {
+                if let Some(hex) = entity.strip_prefix("#x") {
+                    u32::from_str_radix(hex, 16).ok().and_then(::std::char::from_u32)
+                } else if let Some(decimal) = entity.strip_prefix('#') {
+                    decimal.parse::<u32>().ok().and_then(::std::char::from_u32)
+                } else {
+                    None
+                }
+            }
+        };
+        match character {
+            Some(c) => {
+                decoded.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+// Nesting limit, so hostile packets cannot overflow the stack
+const MAX_DEPTH: usize = 256;
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    // Prefix and namespace declarations in scope, innermost last
+    namespaces: Vec<(String, String)>,
+    // Elements open around the current position
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn skip_past(&mut self, end: &str) -> io::Result<()> {
+        match self.rest().find(end) {
+            Some(i) => {
+                self.pos += i + end.len();
+                Ok(())
+            }
+            None => Err(invalid_data(&format!("missing {}", end))),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn name(&mut self) -> &'a str {
+        let rest = self.rest();
+        let end = rest.find(|c: char| c.is_whitespace() || c == '/' || c == '>' || c == '=')
+            .unwrap_or(rest.len());
+        self.pos += end;
+        &rest[..end]
+    }
+
+    fn resolve(&self, name: &str, default: bool) -> String {
+        let prefix = match name.find(':') {
+            Some(i) => &name[..i],
+            None if default => "",
+            None => return String::new(),
+        };
+        if prefix == "xml" {
+            return String::from(XML_NS);
+        }
+        self.namespaces
+            .iter()
+            .rev()
+            .find(|&(p, _)| p == prefix)
+            .map_or(String::new(), |(_, namespace)| namespace.clone())
+    }
+
+    // Skips declarations, comments and processing instructions before markup
+    fn skip_misc(&mut self) -> io::Result<()> {
+        loop {
+            self.skip_whitespace();
+            let rest = self.rest();
+            if rest.starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if rest.starts_with("<!") {
+                self.skip_past(">")?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn element(&mut self) -> io::Result<Element> {
+        if !self.rest().starts_with('<') {
+            return Err(invalid_data("expected an element"));
+        }
+        if self.depth == MAX_DEPTH {
+            return Err(invalid_data("elements nested too deeply"));
+        }
+        self.depth += 1;
+        self.pos += 1;
+        let name = self.name();
+        let mut raw_attributes = vec![];
+        let scope = self.namespaces.len();
+
+        let empty = loop {
+            self.skip_whitespace();
+            let rest = self.rest();
+            if rest.starts_with("/>") {
+                self.pos += 2;
+                break true;
+            } else if rest.starts_with('>') {
+                self.pos += 1;
+                break false;
+            } else if rest.is_empty() {
+                return Err(invalid_data("unterminated tag"));
+            }
+            let attribute = self.name();
+            self.skip_whitespace();
+            if !self.rest().starts_with('=') {
+                return Err(invalid_data(&format!("attribute {} without value", attribute)));
+            }
+            self.pos += 1;
+            self.skip_whitespace();
+            let quote = match self.rest().chars().next() {
+                Some(quote) if quote == '"' || quote == '\'' => quote,
+                _ => return Err(invalid_data("unquoted attribute value")),
+            };
+            self.pos += 1;
+            let end = match self.rest().find(quote) {
+                Some(end) => end,
+                None => return Err(invalid_data("unterminated attribute value")),
+            };
+            let value = decode_entities(&self.rest()[..end]);
+            self.pos += end + 1;
+
+            if attribute == "xmlns" {
+                self.namespaces.push((String::new(), value));
+            } else if let Some(prefix) = attribute.strip_prefix("xmlns:") {
+                self.namespaces.push((String::from(prefix), value));
+            } else {
+                raw_attributes.push((attribute, value));
+            }
+        };
+
+        let mut element = Element {
+            name: String::from(name),
+            namespace: self.resolve(name, true),
+            local: String::from(name.rsplit(':').next().unwrap_or(name)),
+            attributes: raw_attributes.into_iter()
+                .map(|(attribute, value)| {
+                    (String::from(attribute), self.resolve(attribute, false), value)
+                })
+                .collect(),
+            children: vec![],
+            text: String::new(),
+        };
+
+        if !empty {
+            loop {
+                let rest = self.rest();
+                let markup = match rest.find('<') {
+                    Some(markup) => markup,
+                    None => return Err(invalid_data(&format!("unterminated {}", name))),
+                };
+                element.text.push_str(&decode_entities(&rest[..markup]));
+                self.pos += markup;
+                let rest = self.rest();
+                if rest.starts_with("</") {
+                    self.skip_past(">")?;
+                    break;
+                } else if rest.starts_with("<![CDATA[") {
+                    let end = match rest.find("]]>") {
+                        Some(end) => end,
+                        None => return Err(invalid_data("unterminated CDATA")),
+                    };
+                    element.text.push_str(&rest[9..end]);
+                    self.pos += end + 3;
+                } else if rest.starts_with("<!--") {
+                    self.skip_past("-->")?;
+                } else if rest.starts_with("<?") {
+                    self.skip_past("?>")?;
+                } else {
+                    let child = self.element()?;
+                    element.children.push(child);
+                }
+            }
+        }
+        self.namespaces.truncate(scope);
+        self.depth -= 1;
+        Ok(element)
+    }
+}
+
+fn parse_xml(text: &str) -> io::Result<Element> {
+    let mut parser = Parser {
+        text,
+        pos: 0,
+        namespaces: vec![],
+        depth: 0,
+    };
+    parser.skip_misc()?;
+    parser.element()
+}
+
+fn find_rdf(element: &Element) -> Option<&Element> {
+    if element.is_rdf("RDF") {
+        return Some(element);
+    }
+    element.children.iter().filter_map(find_rdf).next()
+}
+
+fn push(properties: &mut Vec<Property>, name: String, namespace: &str, values: Vec<String>) {
+    properties.push(Property {
+        name,
+        namespace: String::from(namespace),
+        values,
+        array: None,
+    });
+}
+
+// Fields of a struct: attributes and property elements of a Description or
+// of a property element with rdf:parseType="Resource"
+fn flatten_fields(element: &Element, path: &str, properties: &mut Vec<Property>) {
+    for &(name, namespace, value) in &element.value_attributes() {
+        push(properties, format!("{}{}", path, name), namespace, vec![value.clone()]);
+    }
+    for child in &element.children {
+        flatten_property(child, &format!("{}{}", path, child.name), properties);
+    }
+}
+
+fn flatten_property(element: &Element, name: &str, properties: &mut Vec<Property>) {
+    if let Some(resource) = element.rdf_attribute("resource") {
+        push(properties, String::from(name), &element.namespace, vec![String::from(resource)]);
+        return;
+    }
+    let is_struct = element.rdf_attribute("parseType") == Some("Resource") ||
+                    !element.value_attributes().is_empty();
+    if is_struct {
+        flatten_fields(element, &format!("{}/", name), properties);
+        return;
+    }
+
+    match element.children.first() {
+        None => {
+            push(properties, String::from(name), &element.namespace, vec![element.text.clone()])
+        }
+        Some(child) if child.is_rdf("Bag") || child.is_rdf("Seq") || child.is_rdf("Alt") => {
+            let items: Vec<&Element> = child.children.iter().filter(|i| i.is_rdf("li")).collect();
+            let simple = items.iter().all(|item| {
+                item.children.is_empty() && item.value_attributes().is_empty() &&
+                item.rdf_attribute("parseType").is_none()
+            });
+            if simple {
+                let values = items.iter().map(|item| item.text.clone()).collect();
+                push(properties, String::from(name), &element.namespace, values);
+                if let Some(property) = properties.last_mut() {
+                    property.array = Some(child.local.clone());
+                }
+            } else {
+                for (i, item) in items.iter().enumerate() {
+                    flatten_property(item, &format!("{}[{}]", name, i + 1), properties);
+                }
+            }
+        }
+        Some(child) if child.is_rdf("Description") => {
+            flatten_fields(child, &format!("{}/", name), properties)
+        }
+        Some(_) => flatten_fields(element, &format!("{}/", name), properties),
+    }
+}
+
+// Properties of an XMP packet, in document order
+pub fn parse(packet: &[u8]) -> io::Result<Vec<Property>> {
+    let text = String::from_utf8_lossy(packet);
+    let root = parse_xml(text.trim_start_matches('\u{feff}').trim_end_matches('\0'))?;
+    let rdf = match find_rdf(&root) {
+        Some(rdf) => rdf,
+        None => return Err(invalid_data("no rdf:RDF element")),
+    };
+
+    let mut properties = vec![];
+    for description in rdf.children.iter().filter(|d| d.is_rdf("Description")) {
+        flatten_fields(description, "", &mut properties);
+    }
+    Ok(properties)
+}
+
+// The XMP packet of IFD0, if any
+pub fn embedded_packet(header: &Header) -> Option<&[u8]> {
+    header.entry("Main", XMP).map(|entry| entry.value_bytes.as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACKET: &str = r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+    xmlns:dc="http://purl.org/dc/elements/1.1/"
+    xmlns:crs="http://ns.adobe.com/camera-raw-settings/1.0/"
+   xmp:Rating="4" crs:Exposure2012="+0.35">
+   <dc:subject>
+    <rdf:Bag>
+     <rdf:li>beach</rdf:li>
+     <rdf:li>sun &amp; sea</rdf:li>
+    </rdf:Bag>
+   </dc:subject>
+   <crs:Look rdf:parseType="Resource">
+    <crs:Name>Adobe Color</crs:Name>
+   </crs:Look>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>"#;
+
+    #[test]
+    fn test_parse() {
+        let properties = parse(PACKET.as_bytes()).unwrap();
+        let names: Vec<&str> = properties.iter().map(|p| p.name.as_ref()).collect();
+        assert_eq!(names,
+                   vec!["xmp:Rating", "crs:Exposure2012", "dc:subject", "crs:Look/crs:Name"]);
+        assert_eq!(properties[0].namespace, "http://ns.adobe.com/xap/1.0/");
+        assert_eq!(properties[2].values, vec!["beach", "sun & sea"]);
+        assert_eq!(properties[2].array, Some(String::from("Bag")));
+        assert_eq!(properties[0].array, None);
+        assert_eq!(properties[3].values, vec!["Adobe Color"]);
+    }
+
+    #[test]
+    fn test_parse_rejects_unterminated() {
+        assert!(parse(b"<x:xmpmeta><rdf:RDF>").is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_deep_nesting() {
+        let packet = "<a>".repeat(100_000);
+        assert_eq!(parse(packet.as_bytes()).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let nested = format!("{}{}", "<a>".repeat(200), "</a>".repeat(200));
+        assert!(parse_xml(&nested).is_ok());
+    }
+}
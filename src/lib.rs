@@ -0,0 +1,10 @@
+extern crate num;
+#[macro_use]
+extern crate lazy_static;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+
+pub mod arw_file;
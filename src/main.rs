@@ -1,27 +1,107 @@
+extern crate arw_info;
 extern crate getopts;
-#[macro_use]
-extern crate lazy_static;
-extern crate num;
 
+use arw_info::arw_file;
+use arw_info::arw_file::batch;
+use arw_info::arw_file::export;
+use arw_info::arw_file::filter;
+use arw_info::arw_file::limits::Limits;
 use getopts::Options;
 use std::env;
 use std::fs::File;
-mod arw_file;
+use std::process;
 
 const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+const COMMANDS: [&str; 11] = ["info",
+                              "preview",
+                              "extract-raw",
+                              "to-dng",
+                              "set",
+                              "strip",
+                              "xmp",
+                              "sidecar",
+                              "icc",
+                              "diff",
+                              "validate"];
 
 fn print_usage(program: &str, opts: Options) {
-    let brief = format!("Usage: {} ARW_FILE [options]", program);
+    let brief = format!("Usage: {} [COMMAND] FILE [options]\n       \
+                         {} [info] FILE|DIR.. [options]\n       \
+                         {} --csv|--tsv -t TAG.. FILE|DIR..\n\n\
+                         Commands:\n    \
+                         info             print all IFD entries (default)\n    \
+                         preview [INDEX]  list embedded JPEGs, or extract the one at INDEX\n    \
+                         extract-raw      write the raw CFA data as 16 bit PGM or TIFF\n    \
+                         to-dng           convert to DNG, written next to the input\n    \
+                         set TAG=VALUE..  overwrite values in place when they fit their slot\n    \
+                         strip            copy without GPS, serial numbers and owner names\n    \
+                         xmp              print embedded XMP merged with the sidecar\n    \
+                         sidecar          write an XMP sidecar from the Exif and Sony tags\n    \
+                         icc              summarize the ICC profile, -o saves it to a file\n    \
+                         diff OTHER       print tags added, removed and changed in OTHER\n    \
+                         validate         check against TIFF 6.0 and Exif 2.3, exit 1 on errors",
+                        program,
+                        program,
+                        program);
     println!("arw_info {}\n", VERSION);
     print!("{}", opts.usage(&brief));
 }
 
+// A number of bytes, with an optional K, M or G suffix for powers of 1024
+fn parse_size(text: &str) -> Option<u64> {
+    let (digits, shift) = match text.chars().last() {
+        Some('K') | Some('k') => (&text[..text.len() - 1], 10),
+        Some('M') | Some('m') => (&text[..text.len() - 1], 20),
+        Some('G') | Some('g') => (&text[..text.len() - 1], 30),
+        _ => (text, 0),
+    };
+    digits.parse::<u64>().ok().and_then(|size| size.checked_mul(1 << shift))
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
     let program = args[0].clone();
     let mut opts = Options::new();
 
     opts.optflag("h", "help", "print this help menu");
+    opts.optflag("f", "focus", "print focus points from the Sony AF tags");
+    opts.optflag("", "json", "print info as a versioned JSON document");
+    opts.optflag("", "csv", "print a CSV table of the -t tags, one row per input file");
+    opts.optflag("", "tsv", "like --csv, tab separated");
+    opts.optflagopt("G", "", "with -s, print exiftool family 1 groups (-G1)", "1");
+    opts.optflag("s", "", "print exiftool -s style tag lines");
+    opts.optflag("X", "", "print exiftool -X style RDF/XML");
+    opts.optflag("", "layout", "print the byte ranges of the structures, gaps and overlaps");
+    opts.optmulti("t", "tag", "tag to print, by label or id, * and ? allowed", "TAG");
+    opts.optopt("o", "output", "write extracted data to FILE instead of stdout", "FILE");
+    opts.optopt("", "format", "extract-raw output format, inferred from --output", "pgm|tiff");
+    opts.optflag("c", "crop", "crop extracted raw data to the default crop area");
+    opts.optflag("", "remove-maker-note", "strip the MakerNote as well");
+    opts.optopt("", "sidecar", "XMP sidecar to merge, by default the .xmp next to it", "FILE");
+    opts.optflag("", "prefer-sidecar", "let sidecar values override embedded XMP values");
+    opts.optmulti("",
+                  "ifd",
+                  "IFD to print (Exif, GPS, MakerNote, IFD1..), or holding the tags to set",
+                  "IFD");
+    opts.optmulti("", "exclude", "tag to leave out, by label or id", "TAG");
+    opts.optflag("",
+                 "ignore-volatile",
+                 "diff without dates, serial numbers, sequence numbers and offsets");
+    opts.optflag("r", "recursive", "read the files in subdirectories of DIR too");
+    opts.optmulti("",
+                  "ext",
+                  "extension of the files read from DIR, by default those of raw, TIFF, JPEG \
+                   and HEIF files",
+                  "EXT");
+    opts.optopt("j", "jobs", "number of files read in parallel, one per CPU by default", "N");
+    opts.optopt("",
+                "max-value-size",
+                "largest tag value read, 64 MiB by default, K, M and G suffixes allowed",
+                "BYTES");
+    opts.optopt("",
+                "max-total-size",
+                "most bytes of tag values read from a file, 256 MiB by default",
+                "BYTES");
 
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
@@ -33,13 +113,89 @@ fn main() {
         return;
     }
 
-    let input = if !matches.free.is_empty() {
-        matches.free[0].clone()
+    let (command, free) = match matches.free.first().map(|c| c.as_ref()) {
+        Some(command) if COMMANDS.contains(&command) => (String::from(command), &matches.free[1..]),
+        _ => (String::from("info"), &matches.free[..]),
+    };
+
+    let input = if !free.is_empty() {
+        free[0].clone()
     } else {
         print_usage(&program, opts);
         return;
     };
 
+    let mut limits = Limits::default();
+    if let Some(size) = matches.opt_str("max-value-size") {
+        match parse_size(&size) {
+            Some(size) => limits.max_value_bytes = size,
+            None => {
+                print_usage(&program, opts);
+                return;
+            }
+        }
+    }
+    if let Some(size) = matches.opt_str("max-total-size") {
+        match parse_size(&size) {
+            Some(size) => limits.max_total_bytes = size,
+            None => {
+                print_usage(&program, opts);
+                return;
+            }
+        }
+    }
+    let mut batch_options = batch::Options {
+        recursive: matches.opt_present("r"),
+        extensions: matches.opt_strs("ext"),
+        limits,
+        ..batch::Options::default()
+    };
+    if let Some(jobs) = matches.opt_str("j") {
+        match jobs.parse() {
+            Ok(jobs) if jobs > 0 => batch_options.jobs = jobs,
+            _ => {
+                print_usage(&program, opts);
+                return;
+            }
+        }
+    }
+
+    let filter = filter::Filter {
+        tags: matches.opt_strs("t"),
+        ifds: matches.opt_strs("ifd"),
+        exclude: matches.opt_strs("exclude"),
+    };
+    let batch_result = if matches.opt_present("csv") || matches.opt_present("tsv") {
+        let separator = if matches.opt_present("tsv") { '\t' } else { ',' };
+        Some(arw_file::table(free, &batch_options, &matches.opt_strs("t"), separator))
+    } else if command == "info" {
+        let format = if matches.opt_present("json") {
+            arw_file::InfoFormat::Json
+        } else if matches.opt_present("layout") {
+            arw_file::InfoFormat::Layout
+        } else if matches.opt_present("X") {
+            arw_file::InfoFormat::Rdf
+        } else if matches.opt_present("s") || matches.opt_present("G") {
+            arw_file::InfoFormat::Exiftool { groups: matches.opt_present("G") }
+        } else {
+            arw_file::InfoFormat::Text
+        };
+        Some(arw_file::info_files(free, &batch_options, matches.opt_present("f"), format, &filter))
+    } else if command == "validate" {
+        Some(arw_file::validate_files(free, &batch_options))
+    } else {
+        None
+    };
+    match batch_result {
+        Some(Ok(0)) => return,
+        Some(Ok(_)) => process::exit(1),
+        Some(Err(err)) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+        None => {}
+    }
+
     match File::open(&input) {
         Err(err) => {
             println!("{:?}", err);
@@ -47,5 +203,80 @@ fn main() {
         }
         Ok(_) => {}
     }
-    arw_file::info(&input);
+
+    let output = matches.opt_str("o");
+
+    let result = match command.as_ref() {
+        "preview" => {
+            let index = match free.get(1).map(|index| index.parse::<usize>()) {
+                Some(Ok(index)) => Some(index),
+                Some(Err(_)) => {
+                    print_usage(&program, opts);
+                    return;
+                }
+                None => None,
+            };
+            arw_file::preview(&input, index, output.as_ref().map(|o| o.as_ref()), limits)
+        }
+        "extract-raw" => {
+            let format_name = matches.opt_str("format")
+                .or_else(|| output.as_ref().and_then(|o| o.rsplit('.').next().map(String::from)))
+                .unwrap_or_else(|| String::from("pgm"));
+            let format = match export::RawFormat::from_name(&format_name) {
+                Some(format) => format,
+                None if matches.opt_present("format") => {
+                    print_usage(&program, opts);
+                    return;
+                }
+                None => export::RawFormat::Pgm,
+            };
+            arw_file::extract_raw(&input,
+                                  format,
+                                  matches.opt_present("c"),
+                                  output.as_ref().map(|o| o.as_ref()),
+                                  limits)
+        }
+        "set" => {
+            if free.len() < 2 {
+                print_usage(&program, opts);
+                return;
+            }
+            arw_file::set_tags(&input,
+                               &free[1..],
+                               matches.opt_str("ifd").as_ref().map(|t| t.as_ref()),
+                               limits)
+        }
+        "strip" => {
+            arw_file::strip(&input,
+                            output.as_ref().map(|o| o.as_ref()),
+                            matches.opt_present("remove-maker-note"),
+                            limits)
+        }
+        "xmp" => {
+            arw_file::xmp(&input,
+                          matches.opt_str("sidecar").as_ref().map(|s| s.as_ref()),
+                          matches.opt_present("prefer-sidecar"),
+                          limits)
+        }
+        "sidecar" => arw_file::write_sidecar(&input, output.as_ref().map(|o| o.as_ref()), limits),
+        "icc" => arw_file::icc(&input, output.as_ref().map(|o| o.as_ref()), limits),
+        "to-dng" => arw_file::to_dng(&input, output.as_ref().map(|o| o.as_ref()), limits),
+        "diff" => {
+            if free.len() != 2 {
+                print_usage(&program, opts);
+                return;
+            }
+            arw_file::diff(&input,
+                           &free[1],
+                           matches.opt_present("ignore-volatile"),
+                           &filter,
+                           limits)
+        }
+        _ => unreachable!(),
+    };
+
+    if let Err(err) = result {
+        eprintln!("{}: {}", input, err);
+        process::exit(1);
+    }
 }